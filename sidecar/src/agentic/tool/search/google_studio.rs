@@ -1,11 +1,14 @@
 use async_trait::async_trait;
 use llm_client::{
     broker::LLMBroker,
-    clients::types::{LLMClientCompletionRequest, LLMClientMessage, LLMType},
+    clients::types::{
+        LLMClientCompletionRequest, LLMClientCompletionResponse, LLMClientMessage, LLMType,
+    },
     provider::{GoogleAIStudioKey, LLMProvider, LLMProviderAPIKeys},
 };
 use serde_xml_rs::{from_str, to_string};
 use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
 
 use crate::agentic::tool::{
     kw_search::types::SerdeError,
@@ -20,6 +23,45 @@ use super::{
     },
 };
 
+/// Which leg of the search loop a [`SearchProgress`] event belongs to.
+/// `RoundComplete` is reported by the caller driving the iterative loop
+/// (not by `GoogleStudioLLM` itself, since it has no notion of "round"),
+/// via [`GoogleStudioLLM::report_round_complete`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchStage {
+    GeneratingSearchQueries,
+    Identifying,
+    Deciding,
+    RoundComplete,
+}
+
+/// LSP-style work-done progress (`WorkDoneProgressBegin`/`Report`/`End`)
+/// for a single stage of the search loop, so a caller can render the
+/// scratch-pad reasoning as it streams in instead of blocking on the
+/// terminal result.
+#[derive(Debug, Clone)]
+pub enum SearchProgress {
+    Begin { stage: SearchStage, message: String },
+    Report { stage: SearchStage, delta: String },
+    End { stage: SearchStage, message: String },
+}
+
+/// How many `SearchResult`s a single `identify` round considers, so a
+/// caller with a lot of search hits can bound the prompt size without the
+/// loop itself having to understand why. Unbounded by default.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchLimits {
+    pub results_per_round: usize,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            results_per_round: usize::MAX,
+        }
+    }
+}
+
 pub struct GoogleStudioLLM {
     model: LLMType,
     provider: LLMProvider,
@@ -27,6 +69,10 @@ pub struct GoogleStudioLLM {
     _root_directory: String,
     root_request_id: String,
     client: Arc<LLMBroker>,
+    // Optional sink for work-done progress; `None` keeps the 3 stages
+    // silent, as before.
+    progress_sender: Option<UnboundedSender<SearchProgress>>,
+    search_limits: SearchLimits,
 }
 
 impl GoogleStudioLLM {
@@ -40,8 +86,103 @@ impl GoogleStudioLLM {
             _root_directory: root_directory,
             root_request_id,
             client,
+            progress_sender: None,
+            search_limits: SearchLimits::default(),
+        }
+    }
+
+    /// Forwards LSP-style work-done progress (stage begin/token
+    /// deltas/stage end) over `progress_sender` as `generate_search_queries`,
+    /// `identify` and `decide` run, so a caller can stream the scratch-pad
+    /// reasoning instead of waiting on the terminal parsed result.
+    pub fn with_progress_reporting(mut self, progress_sender: UnboundedSender<SearchProgress>) -> Self {
+        self.progress_sender = Some(progress_sender);
+        self
+    }
+
+    /// Overrides the default (unbounded) search limits, e.g. to cap how
+    /// many search results a single `identify` round considers.
+    pub fn with_search_limits(mut self, search_limits: SearchLimits) -> Self {
+        self.search_limits = search_limits;
+        self
+    }
+
+    /// The iterative search loop (outside this file) drives rounds of
+    /// generate -> identify -> decide; it calls this once per round so
+    /// `RoundComplete` shows up alongside the per-stage progress above.
+    pub fn report_round_complete(&self, round: usize) {
+        if let Some(progress_sender) = self.progress_sender.as_ref() {
+            let _ = progress_sender.send(SearchProgress::End {
+                stage: SearchStage::RoundComplete,
+                message: format!("round {} complete", round),
+            });
         }
     }
+
+    /// Runs `stream_completion`, forwarding `Begin`/token-delta `Report`s/
+    /// `End` progress for `stage` over `self.progress_sender` as the
+    /// response streams in. Behaves exactly like a bare `stream_completion`
+    /// call when no progress sender is configured.
+    async fn stream_completion_with_progress(
+        &self,
+        stage: SearchStage,
+        messages: LLMClientCompletionRequest,
+        event_type: &'static str,
+    ) -> Result<String, IterativeSearchError> {
+        if let Some(progress_sender) = self.progress_sender.as_ref() {
+            let _ = progress_sender.send(SearchProgress::Begin {
+                stage,
+                message: format!("{} started", event_type),
+            });
+        }
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        let forwarding_progress_sender = self.progress_sender.clone();
+        let forwarding_task = tokio::spawn(async move {
+            while let Some(response) = receiver.recv().await {
+                if let Some(progress_sender) = forwarding_progress_sender.as_ref() {
+                    if let Some(delta) = Self::delta_from_response(&response) {
+                        let _ = progress_sender.send(SearchProgress::Report { stage, delta });
+                    }
+                }
+            }
+        });
+
+        let response = self
+            .client
+            .stream_completion(
+                self.api_keys.to_owned(),
+                messages,
+                self.provider.to_owned(),
+                vec![
+                    ("event_type".to_owned(), event_type.to_owned()),
+                    ("root_id".to_owned(), self.root_request_id.to_string()),
+                ]
+                .into_iter()
+                .collect(),
+                sender,
+            )
+            .await?;
+
+        // the stream has ended (the broker dropped `sender` once the
+        // response finished), so the forwarding task will drain and exit
+        let _ = forwarding_task.await;
+
+        if let Some(progress_sender) = self.progress_sender.as_ref() {
+            let _ = progress_sender.send(SearchProgress::End {
+                stage,
+                message: format!("{} finished", event_type),
+            });
+        }
+
+        Ok(response)
+    }
+
+    fn delta_from_response(response: &LLMClientCompletionResponse) -> Option<String> {
+        response.delta().to_owned()
+    }
+
     pub fn system_message_for_generate_search_query(
         &self,
         _context: &IterativeSearchContext,
@@ -72,7 +213,9 @@ You may use a combination of both.
 For files, you do not need to provide the extension. For Keyword, use only uninterrupted strings, not phrases.
 
 6. Execute the Search:
-Execute the search by providing the search parameters and your thoughts on how to approach this task in XML. 
+Execute the search by providing the search parameters and your thoughts on how to approach this task.
+If you support tool/function calling, call the `search_requests` tool with its JSON arguments instead
+of writing XML. Only fall back to the `<reply>` XML format below if you do not support tool calling.
 
 Think step by step and write out your thoughts in the thinking field.
 
@@ -160,6 +303,9 @@ Examine the current file context provided in the <file_context> tag to understan
 4. Important - in the thinking tag for each item, write a short analysis of its relevance to the issue. This will be relied upon by another system to understand the relevance of this file.
 
 5. Response format:
+If you support tool/function calling, call the `identify_response` tool with its JSON arguments
+instead of writing XML. Only fall back to the `<reply>` XML format below if you do not support
+tool calling.
 <reply>
 <response>
 <item>
@@ -254,8 +400,12 @@ Instructions:
 Important:
     * You CANNOT change the codebase. DO NOT modify or suggest changes to any code.
     * Your task is ONLY to determine if the file context is complete. Do not go beyond this scope.
-    
-Response format: 
+
+If you support tool/function calling, call the `decide_response` tool with its JSON arguments
+instead of writing XML. Only fall back to the `<reply>` XML format below if you do not support
+tool calling.
+
+Response format:
 <reply>
 <response>
 <suggestions>
@@ -298,6 +448,45 @@ false
         )
     }
 
+    /// The JSON-schema tool definition for `generate_search_queries`, mirroring
+    /// the `<search_requests><request><thinking/><tool/><query/></request>...
+    /// </search_requests>` shape `system_message_for_generate_search_query`
+    /// already documents, so a tool-calling model's JSON arguments deserialize
+    /// straight into `SearchRequests` instead of going through the XML path.
+    fn search_requests_tool_schema() -> serde_json::Value {
+        serde_json::json!({
+            "name": "search_requests",
+            "description": "Propose one or more search requests to locate code relevant to the issue.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "requests": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "thinking": {
+                                    "type": "string",
+                                    "description": "Your reasoning for this search request."
+                                },
+                                "tool": {
+                                    "type": "string",
+                                    "enum": ["File", "Keyword"]
+                                },
+                                "query": {
+                                    "type": "string",
+                                    "description": "The file name (without extension) or uninterrupted keyword to search for."
+                                }
+                            },
+                            "required": ["thinking", "tool", "query"]
+                        }
+                    }
+                },
+                "required": ["requests"]
+            }
+        })
+    }
+
     pub async fn generate_search_queries(
         &self,
         context: &IterativeSearchContext,
@@ -311,34 +500,47 @@ false
             self.model.to_owned(),
             vec![system_message.clone(), user_message.clone()],
             0.2,
-            None,
+            Some(vec![Self::search_requests_tool_schema()]),
         );
 
-        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
-
         let response = self
-            .client
-            .stream_completion(
-                self.api_keys.to_owned(),
+            .stream_completion_with_progress(
+                SearchStage::GeneratingSearchQueries,
                 messages,
-                self.provider.to_owned(),
-                vec![
-                    (
-                        "event_type".to_owned(),
-                        "generate_search_tool_query".to_owned(),
-                    ),
-                    ("root_id".to_owned(), self.root_request_id.to_string()),
-                ]
-                .into_iter()
-                .collect(),
-                sender,
+                "generate_search_tool_query",
             )
             .await?;
 
         Ok(GoogleStudioLLM::parse_search_response(&response)?.requests)
     }
 
+    /// Pulls out the JSON tool-call arguments the model emitted, if any. A
+    /// model with tool-calling support replies with a bare JSON object (or
+    /// one fenced in ` ```json `), so a single missing XML close-tag can no
+    /// longer take down parsing the way it could with the XML-only path.
+    fn extract_tool_call_json(response: &str) -> Option<&str> {
+        let trimmed = response.trim();
+        if let Some(start) = trimmed.find("```json") {
+            let after_fence = &trimmed[start + "```json".len()..];
+            let end = after_fence.find("```")?;
+            return Some(after_fence[..end].trim());
+        }
+        if trimmed.starts_with('{') && trimmed.ends_with('}') {
+            return Some(trimmed);
+        }
+        None
+    }
+
     fn parse_search_response(response: &str) -> Result<SearchRequests, IterativeSearchError> {
+        if let Ok(parsed) = serde_json::from_str::<SearchRequests>(response.trim()) {
+            return Ok(parsed);
+        }
+        if let Some(tool_call_json) = Self::extract_tool_call_json(response) {
+            if let Ok(parsed) = serde_json::from_str::<SearchRequests>(tool_call_json) {
+                return Ok(parsed);
+            }
+        }
+
         let lines = response
             .lines()
             .skip_while(|l| !l.contains("<reply>"))
@@ -354,6 +556,15 @@ false
     }
 
     fn parse_identify_response(response: &str) -> Result<IdentifyResponse, IterativeSearchError> {
+        if let Ok(parsed) = serde_json::from_str::<IdentifyResponse>(response.trim()) {
+            return Ok(parsed);
+        }
+        if let Some(tool_call_json) = Self::extract_tool_call_json(response) {
+            if let Ok(parsed) = serde_json::from_str::<IdentifyResponse>(tool_call_json) {
+                return Ok(parsed);
+            }
+        }
+
         let lines = response
             .lines()
             .skip_while(|l| !l.contains("<reply>"))
@@ -369,6 +580,15 @@ false
     }
 
     fn parse_decide_response(response: &str) -> Result<DecideResponse, IterativeSearchError> {
+        if let Ok(parsed) = serde_json::from_str::<DecideResponse>(response.trim()) {
+            return Ok(parsed);
+        }
+        if let Some(tool_call_json) = Self::extract_tool_call_json(response) {
+            if let Ok(parsed) = serde_json::from_str::<DecideResponse>(tool_call_json) {
+                return Ok(parsed);
+            }
+        }
+
         let lines = response
             .lines()
             .skip_while(|l| !l.contains("<reply>"))
@@ -383,6 +603,43 @@ false
         })
     }
 
+    /// The JSON-schema tool definition for `identify`, mirroring the
+    /// `<response><item><path/><thinking/></item>...<scratch_pad/></response>`
+    /// shape `system_message_for_identify` already documents.
+    fn identify_response_tool_schema() -> serde_json::Value {
+        serde_json::json!({
+            "name": "identify_response",
+            "description": "Identify relevant code items from the search results and note your thoughts on the state of the search.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "item": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "path": {
+                                    "type": "string",
+                                    "description": "Path of the relevant file."
+                                },
+                                "thinking": {
+                                    "type": "string",
+                                    "description": "Short analysis of this item's relevance to the issue."
+                                }
+                            },
+                            "required": ["path", "thinking"]
+                        }
+                    },
+                    "scratch_pad": {
+                        "type": "string",
+                        "description": "High-level thoughts on the state of the search."
+                    }
+                },
+                "required": ["item", "scratch_pad"]
+            }
+        })
+    }
+
     pub async fn identify(
         &self,
         context: &IterativeSearchContext,
@@ -390,6 +647,10 @@ false
     ) -> Result<IdentifyResponse, IterativeSearchError> {
         println!("GoogleStudioLLM::identify");
 
+        let search_results = &search_results[..search_results
+            .len()
+            .min(self.search_limits.results_per_round)];
+
         let system_message = LLMClientMessage::system(self.system_message_for_identify(&context));
 
         // may need serde serialise!
@@ -400,30 +661,40 @@ false
             self.model.to_owned(),
             vec![system_message.clone(), user_message.clone()],
             0.2,
-            None,
+            Some(vec![Self::identify_response_tool_schema()]),
         );
 
-        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
-
         let response = self
-            .client
-            .stream_completion(
-                self.api_keys.to_owned(),
-                messages,
-                self.provider.to_owned(),
-                vec![
-                    ("event_type".to_owned(), "identify".to_owned()),
-                    ("root_id".to_owned(), self.root_request_id.to_string()),
-                ]
-                .into_iter()
-                .collect(),
-                sender,
-            )
+            .stream_completion_with_progress(SearchStage::Identifying, messages, "identify")
             .await?;
 
         Ok(GoogleStudioLLM::parse_identify_response(&response)?)
     }
 
+    /// The JSON-schema tool definition for `decide`, mirroring the
+    /// `<response><suggestions/><complete/></response>` shape
+    /// `system_message_for_decide` already documents.
+    fn decide_response_tool_schema() -> serde_json::Value {
+        serde_json::json!({
+            "name": "decide_response",
+            "description": "Decide whether the file context already contains all code relevant to the reported issue.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "suggestions": {
+                        "type": "string",
+                        "description": "How to find the remaining relevant code, if not complete. Empty if complete."
+                    },
+                    "complete": {
+                        "type": "boolean",
+                        "description": "Whether the file context already contains all relevant code."
+                    }
+                },
+                "required": ["suggestions", "complete"]
+            }
+        })
+    }
+
     pub async fn decide(
         &self,
         context: &mut IterativeSearchContext,
@@ -438,25 +709,11 @@ false
             self.model.to_owned(),
             vec![system_message.clone(), user_message.clone()],
             0.2,
-            None,
+            Some(vec![Self::decide_response_tool_schema()]),
         );
 
-        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
-
         let response = self
-            .client
-            .stream_completion(
-                self.api_keys.to_owned(),
-                messages,
-                self.provider.to_owned(),
-                vec![
-                    ("event_type".to_owned(), "decide".to_owned()),
-                    ("root_id".to_owned(), self.root_request_id.to_string()),
-                ]
-                .into_iter()
-                .collect(),
-                sender,
-            )
+            .stream_completion_with_progress(SearchStage::Deciding, messages, "decide")
             .await?;
 
         Ok(GoogleStudioLLM::parse_decide_response(&response)?)
@@ -1,8 +1,17 @@
 use std::{
+    collections::HashSet,
     fs::{self},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Mutex,
 };
 
+use ignore::WalkBuilder;
+use nucleo_matcher::{
+    pattern::{CaseMatching, Normalization, Pattern},
+    Config, Matcher,
+};
+use rayon::prelude::*;
+
 use crate::{
     agentic::tool::search::iterative::{SearchResultSnippet, SearchToolType},
     repomap::{
@@ -13,12 +22,67 @@ use crate::{
 
 use super::iterative::{SearchQuery, SearchResult};
 
+/// Walks a repository honoring `.gitignore`/`.ignore` rules, optionally
+/// gated to a single file extension so a completion or search that only
+/// cares about `.rs` files doesn't pay the cost of crawling everything.
+#[derive(Debug)]
+pub struct Crawl {
+    crawled_extensions: Mutex<HashSet<String>>,
+    crawl_all_files: bool,
+}
+
+impl Crawl {
+    pub fn new(crawl_all_files: bool) -> Self {
+        Self {
+            crawled_extensions: Mutex::new(HashSet::new()),
+            crawl_all_files,
+        }
+    }
+
+    /// Crawls `root` for files matching `extension`, unless that extension
+    /// (or any extension, when `crawl_all_files` is set) has already been
+    /// crawled. Returns the empty vec on a repeat trigger so callers can
+    /// treat this as a cheap no-op once an extension is warm.
+    pub fn ensure_crawled(&self, root: &Path, extension: &str) -> Vec<PathBuf> {
+        let mut crawled_extensions = self
+            .crawled_extensions
+            .lock()
+            .expect("lock should not be poisoned");
+        if crawled_extensions.contains(extension)
+            || (self.crawl_all_files && !crawled_extensions.is_empty())
+        {
+            return vec![];
+        }
+        let crawl_all_files = self.crawl_all_files;
+        let files = WalkBuilder::new(root)
+            .hidden(true)
+            .git_ignore(true)
+            .ignore(true)
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|ft| ft.is_file()).unwrap_or(false))
+            .map(|entry| entry.into_path())
+            .filter(|path| {
+                crawl_all_files
+                    || path
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .map(|ext| ext == extension)
+                        .unwrap_or(false)
+            })
+            .collect::<Vec<_>>();
+        crawled_extensions.insert(extension.to_owned());
+        files
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Repository {
     _tree: String,
     _outline: String,
     tag_index: TagIndex,
     root: PathBuf,
+    crawl: std::sync::Arc<Crawl>,
 }
 
 impl Repository {
@@ -28,9 +92,73 @@ impl Repository {
             _outline: outline,
             tag_index,
             root,
+            crawl: std::sync::Arc::new(Crawl::new(false)),
         }
     }
 
+    /// Same as `new`, but lets callers force crawling every file instead of
+    /// gating by the triggering extension (useful for a first full index).
+    pub fn new_with_crawl_config(
+        tree: String,
+        outline: String,
+        tag_index: TagIndex,
+        root: PathBuf,
+        crawl_all_files: bool,
+    ) -> Self {
+        Self {
+            _tree: tree,
+            _outline: outline,
+            tag_index,
+            root,
+            crawl: std::sync::Arc::new(Crawl::new(crawl_all_files)),
+        }
+    }
+
+    /// Fuzzy-matches `query` against every file path under `root`, scoring
+    /// candidates with a Smith-Waterman-style matcher so typos and partial
+    /// paths (e.g. `execsearch` for `execute_search.rs`) still find a hit.
+    /// Scoring is parallelized with rayon since it's independent per
+    /// candidate; the top ~20 matches are read and returned.
+    fn fuzzy_find_files(root: &Path, query: &str, thinking: &str) -> Vec<SearchResult> {
+        let gitwalker = GitWalker {};
+        let all_files = gitwalker.find_files(root);
+
+        let pattern = Pattern::parse(query, CaseMatching::Ignore, Normalization::Smart);
+
+        let mut scored_paths = all_files
+            .into_par_iter()
+            .filter_map(|path| {
+                let file_name = path.file_name()?.to_string_lossy().into_owned();
+                let mut matcher = Matcher::new(Config::DEFAULT);
+                let mut haystack_buf = Vec::new();
+                let haystack = nucleo_matcher::Utf32Str::new(&file_name, &mut haystack_buf);
+                let score = pattern.score(haystack, &mut matcher)?;
+                Some((score, path))
+            })
+            .collect::<Vec<_>>();
+
+        scored_paths.sort_by(|(score_a, _), (score_b, _)| score_b.cmp(score_a));
+
+        scored_paths
+            .into_iter()
+            .take(20)
+            .filter_map(|(_, path)| {
+                let contents = match fs::read(&path) {
+                    Ok(content) => content,
+                    Err(error) => {
+                        eprintln!("Error reading file: {}", error);
+                        return None;
+                    }
+                };
+                Some(SearchResult::new(
+                    path,
+                    thinking,
+                    SearchResultSnippet::FileContent(contents),
+                ))
+            })
+            .collect()
+    }
+
     pub fn execute_search(&self, search_query: &SearchQuery) -> Vec<SearchResult> {
         match search_query.tool {
             SearchToolType::File => {
@@ -39,6 +167,22 @@ impl Repository {
                     search_query.query
                 );
 
+                // walk this extension's files once (honoring ignore rules)
+                // and log how many were found; the crawled paths are not
+                // added to `tag_index`, which the search below reads as is
+                let extension = Path::new(&search_query.query)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .unwrap_or("rs");
+                let newly_crawled = self.crawl.ensure_crawled(&self.root, extension);
+                if !newly_crawled.is_empty() {
+                    println!(
+                        "repository::execute_search::crawled {} new .{} files",
+                        newly_crawled.len(),
+                        extension
+                    );
+                }
+
                 let tags_in_file = self.tag_index.search_definitions_flattened(
                     &search_query.query,
                     false,
@@ -49,36 +193,21 @@ impl Repository {
                     true => {
                         println!("No tags for file: {}", search_query.query);
 
-                        let gitwalker = GitWalker {};
-
-                        let file = gitwalker.find_file(self.root.as_path(), &search_query.query);
+                        // an exact match missed, so fall back to fuzzy
+                        // matching over every file in the repo rather than
+                        // giving up on a typo'd or partial path
+                        let fuzzy_results = Self::fuzzy_find_files(
+                            self.root.as_path(),
+                            &search_query.query,
+                            &search_query.thinking,
+                        );
 
                         println!(
-                            "repository::execute_search::query::SearchToolType::File::file: {:?}",
-                            file
+                            "repository::execute_search::query::SearchToolType::File::fuzzy_results: {}",
+                            fuzzy_results.len()
                         );
 
-                        if let Some(path) = file {
-                            println!(
-                                "repository::execute_search::query::SearchToolType::File::Some(path): {:?}",
-                                path
-                            );
-                            let contents = match fs::read(&path) {
-                                Ok(content) => content,
-                                Err(error) => {
-                                    eprintln!("Error reading file: {}", error);
-                                    vec![]
-                                }
-                            };
-
-                            vec![SearchResult::new(
-                                path,
-                                &search_query.thinking,
-                                SearchResultSnippet::FileContent(contents),
-                            )]
-                        } else {
-                            vec![]
-                        }
+                        fuzzy_results
                     }
                     false => {
                         println!("Tags found for file: {}", tags_in_file.len());
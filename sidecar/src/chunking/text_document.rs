@@ -0,0 +1,76 @@
+use crate::inline_completion::types::OffsetEncoding;
+
+/// Converts a line's length into the number of units `encoding` counts a
+/// column in, since "character 5" means a different byte/code-unit count
+/// depending on which encoding the editor negotiated over LSP:
+/// - `Utf8`: number of bytes
+/// - `Utf16`: number of UTF-16 code units (what VS Code sends)
+/// - `Utf32`: number of Unicode scalar values (one per `char`)
+pub fn character_count_in_encoding(line: &str, encoding: OffsetEncoding) -> usize {
+    match encoding {
+        OffsetEncoding::Utf8 => line.len(),
+        OffsetEncoding::Utf16 => line.encode_utf16().count(),
+        OffsetEncoding::Utf32 => line.chars().count(),
+    }
+}
+
+/// A zero-indexed line/character position in a document, plus the byte
+/// offset it corresponds to so callers that only care about slicing the
+/// underlying string don't have to re-derive it.
+///
+/// `character` is counted in whichever [`OffsetEncoding`] it was created
+/// with - there's no canonical unit stored here, so a `Position` should
+/// only ever be compared against others built with the same encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Position {
+    line: usize,
+    character: usize,
+    // callers that only ever ask a model for `line`/`character` (e.g. a
+    // tool-call schema) have no byte offset to send back; default to `0`
+    // and let them recompute the real value from the document instead of
+    // failing to deserialize entirely.
+    #[serde(default)]
+    byte_offset: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, character: usize, byte_offset: usize) -> Self {
+        Self {
+            line,
+            character,
+            byte_offset,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn character(&self) -> usize {
+        self.character
+    }
+
+    pub fn byte_offset(&self) -> usize {
+        self.byte_offset
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Range {
+    start: Position,
+    end: Position,
+}
+
+impl Range {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    pub fn start_position(&self) -> Position {
+        self.start
+    }
+
+    pub fn end_position(&self) -> Position {
+        self.end
+    }
+}
@@ -1,5 +1,12 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    sync::Arc,
+};
 
+use ignore::WalkBuilder;
 use llm_client::{
     clients::types::LLMType,
     tokenizer::tokenizer::{LLMTokenizer, LLMTokenizerInput},
@@ -8,11 +15,110 @@ use llm_client::{
 use crate::{
     chunking::{editor_parsing::EditorParsing, text_document::Position},
     inline_completion::{
-        document::content::SnippetInformationWithScore, symbols_tracker::SymbolTrackerInline,
-        types::InLineCompletionError,
+        document::content::{SnippetInformation, SnippetInformationWithScore},
+        symbols_tracker::SymbolTrackerInline,
+        types::{
+            cosine_similarity, EmbeddedSnippet, EmbeddingProvider, InLineCompletionError,
+            VectorStore,
+        },
     },
 };
 
+/// Walks the workspace root honoring `.gitignore`/`.ignore`/hidden-file
+/// rules so the symbol tracker can be seeded with files it hasn't had a
+/// chance to observe yet (a freshly opened project, or a relevant file the
+/// user hasn't touched). Gated on the triggering file's extension so a
+/// large monorepo isn't fully walked on every keystroke, unless
+/// `crawl_all_files` is set.
+pub struct CodeBaseCrawler {
+    crawled_extensions: Mutex<HashSet<String>>,
+    crawl_all_files: bool,
+}
+
+impl CodeBaseCrawler {
+    pub fn new(crawl_all_files: bool) -> Self {
+        Self {
+            crawled_extensions: Mutex::new(HashSet::new()),
+            crawl_all_files,
+        }
+    }
+
+    /// Crawls `root` for files matching `extension` (or every file, when
+    /// `crawl_all_files` is set) and feeds their contents into
+    /// `symbol_tracker`, unless this extension has already been crawled.
+    /// Returns the number of files fed in, so callers can skip logging on
+    /// a no-op trigger.
+    pub async fn seed_symbol_tracker(
+        &self,
+        root: &Path,
+        extension: &str,
+        symbol_tracker: &SymbolTrackerInline,
+    ) -> usize {
+        {
+            let mut crawled_extensions = self
+                .crawled_extensions
+                .lock()
+                .expect("lock should not be poisoned");
+            if crawled_extensions.contains(extension) {
+                return 0;
+            }
+            crawled_extensions.insert(extension.to_owned());
+        }
+
+        let crawl_all_files = self.crawl_all_files;
+        let extension = extension.to_owned();
+        let files = WalkBuilder::new(root)
+            .hidden(true)
+            .git_ignore(true)
+            .ignore(true)
+            .build()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|ft| ft.is_file()).unwrap_or(false))
+            .map(|entry| entry.into_path())
+            .filter(|path| {
+                crawl_all_files
+                    || path
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .map(|ext| ext == extension)
+                        .unwrap_or(false)
+            })
+            .collect::<Vec<PathBuf>>();
+
+        let mut fed = 0;
+        for file in files {
+            if let Ok(content) = std::fs::read_to_string(&file) {
+                symbol_tracker
+                    .track_file(file.to_string_lossy().to_string(), content)
+                    .await;
+                fed += 1;
+            }
+        }
+        fed
+    }
+}
+
+/// Bounds on how many snippets `generate_context` includes, so a caller
+/// can request a narrower or wider search per invocation instead of being
+/// stuck with one hard-coded ceiling.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchLimits {
+    /// Max snippets pulled from any single file, so one big file can't
+    /// crowd out the rest of the context.
+    pub per_file_snippets: usize,
+    /// Max snippets included overall, across all files.
+    pub total_snippets: usize,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            per_file_snippets: 10,
+            total_snippets: usize::MAX,
+        }
+    }
+}
+
 /// Creates the codebase context which we want to use
 /// for generating inline-completions
 pub struct CodeBaseContext {
@@ -23,6 +129,22 @@ pub struct CodeBaseContext {
     cursor_position: Position,
     symbol_tracker: Arc<SymbolTrackerInline>,
     editor_parsing: Arc<EditorParsing>,
+    workspace_root: Option<PathBuf>,
+    crawler: Arc<CodeBaseCrawler>,
+    // Optional semantic retrieval over crawled/tracked files; `None` keeps
+    // ranking lexical-only (current behavior).
+    embedding_provider: Option<Arc<dyn EmbeddingProvider>>,
+    vector_store: Option<Arc<dyn VectorStore>>,
+    embedding_top_k: usize,
+    // Content hash of the last version of each file we embedded, so a
+    // re-crawl only re-embeds files the symbol tracker has actually seen
+    // change instead of re-indexing everything on every completion. Shared
+    // (not owned) for the same reason `crawler` is: `CodeBaseContext` is
+    // rebuilt fresh per completion request, so an owned `Mutex` would reset
+    // this cache - and with it the short-circuit in
+    // `index_file_for_semantic_search` - on every keystroke.
+    indexed_content_hashes: Arc<Mutex<HashMap<String, u64>>>,
+    limits: SearchLimits,
 }
 
 pub enum CodebaseContextString {
@@ -59,9 +181,130 @@ impl CodeBaseContext {
             cursor_position,
             symbol_tracker,
             editor_parsing,
+            workspace_root: None,
+            crawler: Arc::new(CodeBaseCrawler::new(false)),
+            embedding_provider: None,
+            vector_store: None,
+            embedding_top_k: 5,
+            // Not shared unless `with_shared_index_cache` is used, so the
+            // dedup guard only actually short-circuits across requests once
+            // a caller opts in by passing a cache that outlives this call.
+            indexed_content_hashes: Arc::new(Mutex::new(HashMap::new())),
+            limits: SearchLimits::default(),
         }
     }
 
+    /// Overrides the default snippet limits (10 per file, unlimited
+    /// overall) for this invocation.
+    pub fn with_search_limits(mut self, limits: SearchLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Injects a content-hash cache that outlives this single completion
+    /// request (e.g. held alongside `symbol_tracker` for the workspace's
+    /// lifetime), so `index_file_for_semantic_search`'s dedup guard actually
+    /// short-circuits across requests instead of resetting every time
+    /// `CodeBaseContext` is rebuilt.
+    pub fn with_shared_index_cache(
+        mut self,
+        indexed_content_hashes: Arc<Mutex<HashMap<String, u64>>>,
+    ) -> Self {
+        self.indexed_content_hashes = indexed_content_hashes;
+        self
+    }
+
+    /// Enables seeding the symbol tracker from a gitignore-aware crawl of
+    /// `workspace_root` before context generation runs, so files the
+    /// tracker hasn't observed yet still contribute snippets. Without
+    /// this, behavior is unchanged (history-only, as before).
+    ///
+    /// Takes `crawler` rather than constructing one, since `CodeBaseContext`
+    /// itself is rebuilt fresh for every completion request - a `crawler`
+    /// owned by this instance would have its `crawled_extensions` cache
+    /// reset on every keystroke, defeating the whole point of the cache.
+    /// Callers should hold one `Arc<CodeBaseCrawler>` for the lifetime of
+    /// the workspace (e.g. alongside `symbol_tracker`) and pass the same
+    /// one in on every request.
+    pub fn with_workspace_crawl(
+        mut self,
+        workspace_root: PathBuf,
+        crawler: Arc<CodeBaseCrawler>,
+    ) -> Self {
+        self.workspace_root = Some(workspace_root);
+        self.crawler = crawler;
+        self
+    }
+
+    /// Enables semantic retrieval of the `top_k` nearest embedded spans to
+    /// the current window, merged with the existing lexical scores.
+    /// Without this, `generate_context` falls back to the lexical-only
+    /// path, so behavior is unchanged when the feature is off.
+    pub fn with_semantic_retrieval(
+        mut self,
+        embedding_provider: Arc<dyn EmbeddingProvider>,
+        vector_store: Arc<dyn VectorStore>,
+        top_k: usize,
+    ) -> Self {
+        self.embedding_provider = Some(embedding_provider);
+        self.vector_store = Some(vector_store);
+        self.embedding_top_k = top_k;
+        self
+    }
+
+    /// Splits `content` on blank lines so each chunk tends to cover one
+    /// item (function, impl block, ...) rather than an arbitrary
+    /// fixed-size window, without pulling in a full tree-sitter parse for
+    /// what's just an embedding granularity choice.
+    fn symbol_aligned_spans(content: &str) -> Vec<String> {
+        content
+            .split("\n\n")
+            .map(|span| span.trim())
+            .filter(|span| !span.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn content_hash(content: &str) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        content.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Embeds and upserts `content`'s spans into the vector store, unless
+    /// this exact content was already indexed for `file_path`.
+    async fn index_file_for_semantic_search(
+        &self,
+        file_path: &str,
+        content: &str,
+        embedding_provider: &Arc<dyn EmbeddingProvider>,
+        vector_store: &Arc<dyn VectorStore>,
+    ) -> Result<(), InLineCompletionError> {
+        let content_hash = Self::content_hash(content);
+        {
+            let mut indexed_content_hashes = self
+                .indexed_content_hashes
+                .lock()
+                .expect("lock should not be poisoned");
+            if indexed_content_hashes.get(file_path) == Some(&content_hash) {
+                return Ok(());
+            }
+            indexed_content_hashes.insert(file_path.to_owned(), content_hash);
+        }
+
+        for span in Self::symbol_aligned_spans(content) {
+            let embedding = embedding_provider.embed(&span).await?;
+            vector_store
+                .upsert(EmbeddedSnippet {
+                    file_path: file_path.to_owned(),
+                    snippet: span,
+                    embedding,
+                })
+                .await;
+        }
+        Ok(())
+    }
+
     pub fn get_context_window_from_current_file(&self) -> String {
         let current_line = self.cursor_position.line();
         let lines = self.file_content.lines().collect::<Vec<_>>();
@@ -83,6 +326,21 @@ impl CodeBaseContext {
             InLineCompletionError::LanguageNotSupported("not_supported".to_owned()),
         )?;
         let current_window_context = self.get_context_window_from_current_file();
+
+        // Seed the symbol tracker from a gitignore-aware crawl of the
+        // workspace before we read its history, so a freshly opened
+        // project (or a relevant file the user hasn't touched) still
+        // contributes snippets instead of contributing nothing.
+        if let Some(workspace_root) = self.workspace_root.as_ref() {
+            let extension = Path::new(&self.file_path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("rs");
+            self.crawler
+                .seed_symbol_tracker(workspace_root, extension, &self.symbol_tracker)
+                .await;
+        }
+
         // Now we try to get the context from the symbol tracker
         let history_files = self.symbol_tracker.get_document_history().await;
         // since these history files are sorted in the order of priority, we can
@@ -90,20 +348,60 @@ impl CodeBaseContext {
 
         let mut relevant_snippets: Vec<SnippetInformationWithScore> = vec![];
         // TODO(skcd): hate hate hate, but there's a mutex lock so this is fine ❤️‍🔥
-        for history_file in history_files.into_iter() {
-            let skip_line = if history_file == self.file_path {
+        for history_file in history_files.iter() {
+            let skip_line = if history_file == &self.file_path {
                 Some(self.cursor_position.line())
             } else {
                 None
             };
             let snippet_information = self
                 .symbol_tracker
-                .get_document_lines(&history_file, &current_window_context, skip_line)
+                .get_document_lines(history_file, &current_window_context, skip_line)
                 .await;
             if let Some(mut snippet_information) = snippet_information {
                 relevant_snippets.append(&mut snippet_information);
             }
         }
+
+        // Semantic retrieval is additive: index whatever the symbol
+        // tracker currently knows about, embed the current window, and
+        // merge the nearest spans in with the lexical scores above. When
+        // no provider/store is configured this is a no-op, so ranking
+        // stays lexical-only (unchanged behavior).
+        if let (Some(embedding_provider), Some(vector_store)) =
+            (self.embedding_provider.as_ref(), self.vector_store.as_ref())
+        {
+            for history_file in history_files.iter() {
+                let content = if history_file == &self.file_path {
+                    self.file_content.clone()
+                } else {
+                    match std::fs::read_to_string(history_file) {
+                        Ok(content) => content,
+                        Err(_) => continue,
+                    }
+                };
+                self.index_file_for_semantic_search(
+                    history_file,
+                    &content,
+                    embedding_provider,
+                    vector_store,
+                )
+                .await?;
+            }
+
+            let query_embedding = embedding_provider.embed(&current_window_context).await?;
+            let nearest = vector_store
+                .nearest(&query_embedding, self.embedding_top_k)
+                .await;
+            for embedded_snippet in nearest {
+                let similarity = cosine_similarity(&query_embedding, &embedded_snippet.embedding);
+                relevant_snippets.push(SnippetInformationWithScore::new(
+                    SnippetInformation::new(embedded_snippet.file_path, embedded_snippet.snippet),
+                    similarity,
+                ));
+            }
+        }
+
         println!("relevant_snippets_len: {:?}", relevant_snippets.len());
         relevant_snippets.sort_by(|a, b| {
             b.score()
@@ -111,20 +409,22 @@ impl CodeBaseContext {
                 .unwrap_or(std::cmp::Ordering::Equal)
         });
 
-        // Now that we have the relevant snippets we can generate the context
-        let mut running_context: Vec<String> = vec![];
-        let mut inlcuded_snippet_from_files: HashMap<String, usize> = HashMap::new();
+        // Budget-pack the snippets instead of greedily appending in raw
+        // score order: each snippet's token cost is counted once (instead
+        // of re-tokenizing the whole running buffer on every iteration,
+        // which made the old loop O(n^2) in tokenizer calls), and we rank
+        // by score-per-token so the budget goes to whichever snippets are
+        // most relevant per token spent. A per-file diversity penalty
+        // divides a snippet's value by its rank within its own file, so
+        // the Nth near-duplicate from one file doesn't crowd out a
+        // single highly relevant snippet from another.
+        let mut candidates = Vec::with_capacity(relevant_snippets.len());
+        let mut occurrences_by_file: HashMap<String, usize> = HashMap::new();
         for snippet in relevant_snippets {
-            let file_path = snippet.file_path();
-            let current_count: usize =
-                *inlcuded_snippet_from_files.get(file_path).unwrap_or(&0) + 1;
-            inlcuded_snippet_from_files.insert(file_path.to_owned(), current_count);
-
-            // we have a strict limit of 10 snippets from each file, if we exceed that we break
-            // this prevents a big file from putting in too much context
-            if current_count > 10 {
-                continue;
-            }
+            let file_path = snippet.file_path().to_owned();
+            let occurrence = *occurrences_by_file.get(&file_path).unwrap_or(&0) + 1;
+            occurrences_by_file.insert(file_path.clone(), occurrence);
+
             let snippet_context = snippet
                 .snippet_information()
                 .snippet()
@@ -132,31 +432,53 @@ impl CodeBaseContext {
                 .map(|snippet| format!("{} {}", language_config.comment_prefix, snippet))
                 .collect::<Vec<_>>()
                 .join("\n");
-            let file_path_header =
-                format!("{} Path: {}", language_config.comment_prefix, file_path,);
+            let file_path_header = format!("{} Path: {}", language_config.comment_prefix, file_path);
             let joined_snippet_context = format!("{}\n{}", file_path_header, snippet_context);
-            running_context.push(joined_snippet_context);
-            let current_context = running_context.join("\n");
-            let tokens_used = self.tokenizer.count_tokens(
+
+            let token_cost = (self.tokenizer.count_tokens(
                 &self.llm_type,
-                LLMTokenizerInput::Prompt(running_context.join("\n")),
-            )?;
-            if token_limit > token_limit {
-                return Ok(CodebaseContextString::TruncatedToLimit(
-                    current_context,
-                    tokens_used as i64,
-                ));
+                LLMTokenizerInput::Prompt(joined_snippet_context.clone()),
+            )? as i64)
+                .max(1);
+            let value_density = snippet.score() / token_cost as f32 / occurrence as f32;
+
+            candidates.push((value_density, file_path, occurrence, token_cost, joined_snippet_context));
+        }
+        candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        let had_candidates = !candidates.is_empty();
+
+        let mut running_context: Vec<String> = vec![];
+        let mut tokens_used_so_far: i64 = 0;
+        for (_value_density, _file_path, occurrence, token_cost, joined_snippet_context) in candidates
+        {
+            if running_context.len() >= self.limits.total_snippets {
+                break;
             }
+            // `occurrence` is this snippet's rank within its file in the
+            // original score order; skip it outright once that exceeds
+            // the per-file cap instead of spending budget on it.
+            if occurrence > self.limits.per_file_snippets {
+                continue;
+            }
+            if tokens_used_so_far + token_cost > token_limit as i64 {
+                continue;
+            }
+
+            tokens_used_so_far += token_cost;
+            running_context.push(joined_snippet_context);
+        }
+
+        if had_candidates && running_context.is_empty() {
+            // nothing fit under the budget at all (the cheapest candidate
+            // alone already overflows it), so there's nothing honest we
+            // can truncate to
+            return Ok(CodebaseContextString::UnableToTruncate);
         }
 
         let prefix_context = running_context.join("\n\n");
-        let used_tokens_for_prefix = self.tokenizer.count_tokens(
-            &self.llm_type,
-            LLMTokenizerInput::Prompt(prefix_context.to_owned()),
-        )?;
         Ok(CodebaseContextString::TruncatedToLimit(
             prefix_context,
-            used_tokens_for_prefix as i64,
+            tokens_used_so_far,
         ))
     }
 }
\ No newline at end of file
@@ -1,24 +1,223 @@
 //! This generates context from the current file
 //! We are not going for grandiose limits right now and will start here
 
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 
+use async_trait::async_trait;
 use llm_client::{clients::types::LLMType, tokenizer::tokenizer::LLMTokenizer};
 use tracing::info;
 
 use crate::{
+    agentic::tool::search::{
+        iterative::{SearchQuery, SearchResultSnippet, SearchToolType},
+        repository::Repository,
+    },
     chunking::{
         editor_parsing::EditorParsing,
         text_document::{Position, Range},
     },
     inline_completion::{
         context::types::{CodeSelection, DocumentLines},
-        types::InLineCompletionError,
+        types::{InLineCompletionError, OffsetEncoding},
     },
 };
 
 use super::types::CurrentFilePrefixSuffix;
 
+/// A source of additional context to enrich fill-in-middle prompts with.
+///
+/// Implementations are free to look anywhere (the current file, recently
+/// touched files, the whole repository, ...) as long as they respect the
+/// `token_budget` they are handed.
+#[async_trait]
+pub trait ContextBackend: Send + Sync {
+    async fn get_context(
+        &self,
+        file_path: &str,
+        position: &Position,
+        token_budget: usize,
+    ) -> Result<Vec<CodeSelection>, InLineCompletionError>;
+}
+
+/// Wraps the existing prefix/suffix expansion logic so it can be selected
+/// like any other backend.
+pub struct CurrentFileContextBackend {
+    tokenizer: Arc<LLMTokenizer>,
+    editor_parsing: Arc<EditorParsing>,
+    llm_type: LLMType,
+}
+
+impl CurrentFileContextBackend {
+    pub fn new(
+        tokenizer: Arc<LLMTokenizer>,
+        editor_parsing: Arc<EditorParsing>,
+        llm_type: LLMType,
+    ) -> Self {
+        Self {
+            tokenizer,
+            editor_parsing,
+            llm_type,
+        }
+    }
+}
+
+#[async_trait]
+impl ContextBackend for CurrentFileContextBackend {
+    async fn get_context(
+        &self,
+        file_path: &str,
+        position: &Position,
+        token_budget: usize,
+    ) -> Result<Vec<CodeSelection>, InLineCompletionError> {
+        // unlike `FillInMiddleCompletionAgent`, which already has the
+        // document's text in the completion request, this backend is
+        // composed generically alongside `RecentFilesContextBackend`/
+        // `RepoWideContextBackend` and only gets a path, so it has to read
+        // the file itself before it can reuse the same expansion logic.
+        let content = std::fs::read_to_string(file_path)
+            .map_err(|err| InLineCompletionError::IoError(file_path.to_owned(), err))?;
+        let document_lines = DocumentLines::from_file_content(&content);
+        let current_file_context = CurrentFileContext::new(
+            file_path.to_owned(),
+            position.clone(),
+            token_budget,
+            self.tokenizer.clone(),
+            self.editor_parsing.clone(),
+            self.llm_type.clone(),
+            // read straight off disk rather than over LSP, so there's no
+            // negotiated encoding to respect here
+            OffsetEncoding::Utf8,
+        );
+        let prefix_suffix = current_file_context.generate_context(&document_lines)?;
+        Ok(vec![prefix_suffix.prefix, prefix_suffix.suffix])
+    }
+}
+
+/// Keeps a small ring-buffer of recently opened/edited files in memory so
+/// completions can draw on context the user just touched even if it never
+/// made it into the repo-wide index.
+pub struct RecentFilesContextBackend {
+    tokenizer: Arc<LLMTokenizer>,
+    llm_type: LLMType,
+    recent_files: Mutex<VecDeque<(String, String)>>,
+    capacity: usize,
+}
+
+impl RecentFilesContextBackend {
+    pub fn new(tokenizer: Arc<LLMTokenizer>, llm_type: LLMType, capacity: usize) -> Self {
+        Self {
+            tokenizer,
+            llm_type,
+            recent_files: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Record that `file_path` was just opened or edited, with its current
+    /// contents. The most recently touched file is always consulted first.
+    pub fn track_file(&self, file_path: String, content: String) {
+        let mut recent_files = self.recent_files.lock().expect("lock should not be poisoned");
+        recent_files.retain(|(path, _)| path != &file_path);
+        recent_files.push_front((file_path, content));
+        while recent_files.len() > self.capacity {
+            recent_files.pop_back();
+        }
+    }
+}
+
+#[async_trait]
+impl ContextBackend for RecentFilesContextBackend {
+    async fn get_context(
+        &self,
+        file_path: &str,
+        position: &Position,
+        token_budget: usize,
+    ) -> Result<Vec<CodeSelection>, InLineCompletionError> {
+        let mut used_tokens = 0;
+        let mut selections = vec![];
+        let recent_files = self
+            .recent_files
+            .lock()
+            .expect("lock should not be poisoned")
+            .clone();
+        for (recent_file_path, content) in recent_files.into_iter() {
+            if recent_file_path == file_path {
+                // the current file is already covered by the prefix/suffix
+                // expansion, so skip it here
+                continue;
+            }
+            let token_count = self
+                .tokenizer
+                .count_tokens_using_tokenizer(&self.llm_type, &content)?;
+            if used_tokens + token_count > token_budget {
+                break;
+            }
+            used_tokens += token_count;
+            selections.push(CodeSelection::new(
+                Range::new(position.clone(), position.clone()),
+                recent_file_path,
+                content,
+            ));
+        }
+        Ok(selections)
+    }
+}
+
+/// Draws context from across the whole repository using the `TagIndex`
+/// already maintained by `Repository`, so completions can reference
+/// definitions that live outside the current buffer entirely.
+pub struct RepoWideContextBackend {
+    repository: Arc<Repository>,
+}
+
+impl RepoWideContextBackend {
+    pub fn new(repository: Arc<Repository>) -> Self {
+        Self { repository }
+    }
+}
+
+#[async_trait]
+impl ContextBackend for RepoWideContextBackend {
+    async fn get_context(
+        &self,
+        file_path: &str,
+        position: &Position,
+        token_budget: usize,
+    ) -> Result<Vec<CodeSelection>, InLineCompletionError> {
+        let search_query = SearchQuery::new(
+            SearchToolType::Keyword,
+            file_path.to_owned(),
+            "repo-wide context for inline completion".to_owned(),
+        );
+        let search_results = self.repository.execute_search(&search_query);
+        let mut selections = vec![];
+        let mut used_tokens = 0;
+        for result in search_results {
+            let snippet = match result.snippet() {
+                SearchResultSnippet::FileContent(content) => {
+                    String::from_utf8_lossy(content).into_owned()
+                }
+                SearchResultSnippet::Tag(tag_name) => tag_name.to_owned(),
+            };
+            // we don't have a tokenizer handy here, so approximate by chars
+            // (4 chars per token); the budget is compared against that
+            // estimate, not a real token count
+            let approx_tokens = snippet.len() / 4;
+            if used_tokens + approx_tokens > token_budget {
+                break;
+            }
+            used_tokens += approx_tokens;
+            selections.push(CodeSelection::new(
+                Range::new(position.clone(), position.clone()),
+                result.path().to_owned(),
+                snippet,
+            ));
+        }
+        Ok(selections)
+    }
+}
+
 // Grabs the current file context from the cursor position
 pub struct CurrentFileContext {
     file_path: String,
@@ -27,6 +226,11 @@ pub struct CurrentFileContext {
     tokenizer: Arc<LLMTokenizer>,
     editor_parsing: Arc<EditorParsing>,
     llm_type: LLMType,
+    // the unit the editor negotiated for position/column math over LSP;
+    // fed into every `start_position_at_line`/`end_position_at_line` call
+    // below so the prefix/suffix ranges land on the columns that editor
+    // actually expects
+    offset_encoding: OffsetEncoding,
 }
 
 impl CurrentFileContext {
@@ -37,6 +241,7 @@ impl CurrentFileContext {
         tokenizer: Arc<LLMTokenizer>,
         editor_parsing: Arc<EditorParsing>,
         llm_type: LLMType,
+        offset_encoding: OffsetEncoding,
     ) -> Self {
         Self {
             file_path,
@@ -45,9 +250,66 @@ impl CurrentFileContext {
             tokenizer,
             llm_type,
             editor_parsing,
+            offset_encoding,
         }
     }
 
+    /// Finds the smallest tree-sitter node whose range contains the cursor,
+    /// then grows outward to parent nodes (closest enclosing pair first)
+    /// until the next parent would blow the token budget. Returns the
+    /// node's `(start_row, end_row)` so the caller can expand prefix/suffix
+    /// to that syntactic boundary instead of a fixed line count. Returns
+    /// `None` when no grammar is available for this file, so the caller
+    /// can fall back to the line-based expansion.
+    fn syntax_aware_line_bounds(
+        &self,
+        document_lines: &DocumentLines,
+        current_line_number: usize,
+    ) -> Option<(usize, usize)> {
+        let language_config = self.editor_parsing.for_file_path(&self.file_path)?;
+        let grammar = language_config.grammar()?;
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(&grammar).ok()?;
+
+        let source = (0..document_lines.len())
+            .map(|line_number| document_lines.get_line(line_number))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let tree = parser.parse(&source, None)?;
+
+        let cursor_point =
+            tree_sitter::Point::new(current_line_number, self.cursor_position.character() as usize);
+        let mut node = tree
+            .root_node()
+            .descendant_for_point_range(cursor_point, cursor_point)?;
+
+        // `None` until the first node that actually fits the token budget,
+        // so a smallest enclosing node that's already too big returns
+        // `None` (triggering the line-based fallback) instead of shipping
+        // an oversized, unchecked node.
+        let mut best_bounds: Option<(usize, usize)> = None;
+        loop {
+            let (start_row, end_row) = (node.start_position().row, node.end_position().row);
+            let candidate_text = (start_row..=end_row.min(document_lines.len().saturating_sub(1)))
+                .map(|line_number| document_lines.get_line(line_number))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let candidate_tokens = self
+                .tokenizer
+                .count_tokens_using_tokenizer(&self.llm_type, &candidate_text)
+                .ok()?;
+            if candidate_tokens > self.token_limit {
+                break;
+            }
+            best_bounds = Some((start_row, end_row));
+            match node.parent() {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+        best_bounds
+    }
+
     pub fn generate_context(
         mut self,
         document_lines: &DocumentLines,
@@ -87,47 +349,65 @@ impl CurrentFileContext {
         // First get the current line's content from the cursor position
         // we need to keep track of the position as well, since its important
         // metadata
-        // expand until we hit the token limit
+        // expand until we hit the token limit, preferring a tree-sitter
+        // driven expansion to the nearest enclosing syntactic scope so we
+        // don't cut off mid-statement; fall back to blind line stepping
+        // when no grammar is available for this file type
         let mut prefix = vec![];
         let mut suffix = vec![];
-        let mut current_token_count = 0;
-
-        let mut iteration_number = 0;
-        let mut prefix_line = current_line_number - 1;
-        let mut suffix_line = current_line_number + 1;
-        while current_token_count < self.token_limit {
-            // we take in the 3:1 ratio, so we prefer strings from the prefix
-            // more over strings from the suffix
-            if iteration_number % 4 != 0 {
-                if prefix_line >= 0 {
-                    let line = document_lines.get_line(prefix_line);
-                    let tokens = self
-                        .tokenizer
-                        .count_tokens_using_tokenizer(&self.llm_type, line)?;
-                    if current_token_count + tokens > self.token_limit {
-                        break;
-                    }
-                    current_token_count += tokens;
-                    prefix.push(line.to_owned());
-                    prefix_line -= 1;
-                }
-            } else {
-                if suffix_line < document_lines.len() {
-                    let line = document_lines.get_line(suffix_line);
-                    let tokens = self
-                        .tokenizer
-                        .count_tokens_using_tokenizer(&self.llm_type, line)?;
-                    if current_token_count + tokens > self.token_limit {
-                        break;
+
+        // `prefix_start` is the first line included in the prefix
+        let (prefix_start, mut suffix_line) = match self
+            .syntax_aware_line_bounds(document_lines, current_line_number)
+        {
+            Some((start_row, end_row)) => (start_row, (end_row + 1).min(document_lines.len())),
+            None => {
+                let mut current_token_count = 0;
+                let mut iteration_number = 0;
+                let mut prefix_line = current_line_number - 1;
+                let mut suffix_line = current_line_number + 1;
+                while current_token_count < self.token_limit {
+                    // we take in the 3:1 ratio, so we prefer strings from the prefix
+                    // more over strings from the suffix
+                    if iteration_number % 4 != 0 {
+                        if prefix_line >= 0 {
+                            let line = document_lines.get_line(prefix_line);
+                            let tokens = self
+                                .tokenizer
+                                .count_tokens_using_tokenizer(&self.llm_type, line)?;
+                            if current_token_count + tokens > self.token_limit {
+                                break;
+                            }
+                            current_token_count += tokens;
+                            prefix_line -= 1;
+                        }
+                    } else {
+                        if suffix_line < document_lines.len() {
+                            let line = document_lines.get_line(suffix_line);
+                            let tokens = self
+                                .tokenizer
+                                .count_tokens_using_tokenizer(&self.llm_type, line)?;
+                            if current_token_count + tokens > self.token_limit {
+                                break;
+                            }
+                            current_token_count += tokens;
+                            suffix_line += 1;
+                        }
                     }
-                    current_token_count += tokens;
-                    suffix.push(line.to_owned());
-                    suffix_line += 1;
                 }
+                (prefix_line + 1, suffix_line)
             }
+        };
+
+        // materialize the chosen line range into prefix/suffix content now
+        // that we know the final bounds, regardless of which path picked them
+        for line_number in prefix_start..current_line_number {
+            prefix.push(document_lines.get_line(line_number).to_owned());
+        }
+        for line_number in (current_line_number + 1)..suffix_line {
+            suffix.push(document_lines.get_line(line_number).to_owned());
         }
 
-        prefix.reverse();
         // push the current line content to the prefix
         prefix.push(current_line.to_owned());
         // now check if we have a possible file path,
@@ -151,8 +431,8 @@ impl CurrentFileContext {
         // line n ... [cursor_line -1.end()]
         let prefix = CodeSelection::new(
             Range::new(
-                document_lines.start_position_at_line(prefix_line + 1),
-                document_lines.end_position_at_line(current_line_number),
+                document_lines.start_position_at_line(prefix_start, self.offset_encoding),
+                document_lines.end_position_at_line(current_line_number, self.offset_encoding),
             ),
             self.file_path.clone(),
             prefix.join("\n"),
@@ -169,8 +449,8 @@ impl CurrentFileContext {
         // line n ... [cursor_line + 1.start()]
         let suffix = CodeSelection::new(
             Range::new(
-                document_lines.start_position_at_line(current_line_number + 1),
-                document_lines.end_position_at_line(suffix_line - 1),
+                document_lines.start_position_at_line(current_line_number + 1, self.offset_encoding),
+                document_lines.end_position_at_line(suffix_line - 1, self.offset_encoding),
             ),
             self.file_path.clone(),
             suffix.join("\n"),
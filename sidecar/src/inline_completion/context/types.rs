@@ -0,0 +1,114 @@
+use crate::chunking::text_document::{character_count_in_encoding, Position, Range};
+use crate::inline_completion::types::OffsetEncoding;
+
+/// A document split into lines, with each line's byte offset into the
+/// full content precomputed so `start_position_at_line`/
+/// `end_position_at_line` don't have to re-scan from the top of the file
+/// on every call.
+pub struct DocumentLines {
+    lines: Vec<String>,
+    line_byte_offsets: Vec<usize>,
+}
+
+impl DocumentLines {
+    pub fn from_file_content(content: &str) -> Self {
+        let mut lines = vec![];
+        let mut line_byte_offsets = vec![];
+        let mut offset = 0;
+        for line in content.split('\n') {
+            line_byte_offsets.push(offset);
+            offset += line.len() + 1;
+            lines.push(line.to_owned());
+        }
+        Self {
+            lines,
+            line_byte_offsets,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn get_line(&self, line_number: usize) -> &str {
+        self.lines
+            .get(line_number)
+            .map(|line| line.as_str())
+            .unwrap_or("")
+    }
+
+    /// The position of the first character on `line_number`. The
+    /// character offset is always `0` regardless of encoding (there's
+    /// nothing before the start of a line to count), but `encoding` is
+    /// still taken so callers thread the same negotiated unit through
+    /// every position on a line, matching `end_position_at_line`.
+    pub fn start_position_at_line(&self, line_number: usize, _encoding: OffsetEncoding) -> Position {
+        let byte_offset = self
+            .line_byte_offsets
+            .get(line_number)
+            .copied()
+            .unwrap_or(0);
+        Position::new(line_number, 0, byte_offset)
+    }
+
+    /// The position just past the last character on `line_number`, with
+    /// `character` counted in `encoding`'s units - e.g. a line ending in a
+    /// multi-byte character reports a different `character` for `Utf8`
+    /// (byte count) than for `Utf16` (what VS Code expects).
+    pub fn end_position_at_line(&self, line_number: usize, encoding: OffsetEncoding) -> Position {
+        let line = self.get_line(line_number);
+        let character = character_count_in_encoding(line, encoding);
+        let byte_offset = self
+            .line_byte_offsets
+            .get(line_number)
+            .copied()
+            .unwrap_or(0)
+            + line.len();
+        Position::new(line_number, character, byte_offset)
+    }
+}
+
+/// A range of source picked out of a file, tagged with the file it came
+/// from so prompt assembly can cite it (e.g. a `// FILEPATH:` comment)
+/// without threading the path through separately.
+#[derive(Debug, Clone)]
+pub struct CodeSelection {
+    range: Range,
+    file_path: String,
+    content: String,
+}
+
+impl CodeSelection {
+    pub fn new(range: Range, file_path: String, content: String) -> Self {
+        Self {
+            range,
+            file_path,
+            content,
+        }
+    }
+
+    pub fn range(&self) -> Range {
+        self.range
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+}
+
+/// The prefix/suffix `CurrentFileContext::generate_context` expanded
+/// around the cursor, ready to be formatted into a fill-in-middle prompt.
+pub struct CurrentFilePrefixSuffix {
+    pub prefix: CodeSelection,
+    pub suffix: CodeSelection,
+}
+
+impl CurrentFilePrefixSuffix {
+    pub fn new(prefix: CodeSelection, suffix: CodeSelection) -> Self {
+        Self { prefix, suffix }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::chunking::text_document::{character_count_in_encoding, Position, Range};
+use crate::inline_completion::context::types::DocumentLines;
+use crate::inline_completion::types::OffsetEncoding;
+
+/// Clamps a cursor `position` whose `character` overruns its line's
+/// actual length (observed from VS Code on lines ending in certain
+/// multi-byte sequences) back onto the line, so downstream position math
+/// never has to special-case an out-of-bounds column.
+pub fn fix_vscode_position(
+    position: Position,
+    document_lines: &DocumentLines,
+    offset_encoding: OffsetEncoding,
+) -> Position {
+    let line = document_lines.get_line(position.line());
+    let max_character = character_count_in_encoding(line, offset_encoding);
+    if position.character() > max_character {
+        Position::new(position.line(), max_character, position.byte_offset())
+    } else {
+        position
+    }
+}
+
+/// The range a completion occupies once inserted at `position`: starts at
+/// `position` and ends wherever `inserted_text` lands once its newlines
+/// have shifted the line and its trailing line's length has shifted the
+/// column - counted in `offset_encoding`'s units so editors negotiating
+/// UTF-8/UTF-32 (not just VS Code's UTF-16) get a range they can apply
+/// without corrupting columns on a line with multibyte characters.
+pub fn insert_range(
+    position: Position,
+    document_lines: DocumentLines,
+    inserted_text: &str,
+    offset_encoding: OffsetEncoding,
+) -> Range {
+    let position = fix_vscode_position(position, &document_lines, offset_encoding);
+    let newline_count = inserted_text.matches('\n').count();
+    let end_position = if newline_count == 0 {
+        Position::new(
+            position.line(),
+            position.character() + character_count_in_encoding(inserted_text, offset_encoding),
+            position.byte_offset() + inserted_text.len(),
+        )
+    } else {
+        let last_line = inserted_text.rsplit('\n').next().unwrap_or("");
+        Position::new(
+            position.line() + newline_count,
+            character_count_in_encoding(last_line, offset_encoding),
+            position.byte_offset() + inserted_text.len(),
+        )
+    };
+    Range::new(position, end_position)
+}
@@ -3,7 +3,9 @@ use std::sync::Arc;
 use axum::Json;
 use llm_client::{
     broker::LLMBroker,
-    clients::types::{LLMClientCompletionStringRequest, LLMType},
+    clients::types::{
+        LLMClientCompletionRequest, LLMClientCompletionStringRequest, LLMClientMessage, LLMType,
+    },
     tokenizer::tokenizer::{LLMTokenizer, LLMTokenizerError},
 };
 use llm_prompts::{
@@ -12,7 +14,10 @@ use llm_prompts::{
 };
 
 use crate::{
-    chunking::editor_parsing::EditorParsing,
+    chunking::{
+        editor_parsing::EditorParsing,
+        text_document::{Position, Range},
+    },
     inline_completion::helpers::fix_vscode_position,
     webserver::{
         inline_completion::{InlineCompletion, InlineCompletionRequest, InlineCompletionResponse},
@@ -25,12 +30,269 @@ use super::{
     helpers::insert_range,
 };
 
+pub use super::context::current_file::{
+    ContextBackend, CurrentFileContextBackend, RecentFilesContextBackend, RepoWideContextBackend,
+};
+
+/// The unit editors use when negotiating LSP position/column offsets.
+/// VS Code always speaks UTF-16, but other editors (Helix, for example)
+/// negotiate UTF-8 or UTF-32 during `initialize`; picking the wrong one
+/// corrupts column math on any line containing multibyte characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OffsetEncoding {
+    Utf8,
+    Utf16,
+    Utf32,
+}
+
+impl Default for OffsetEncoding {
+    fn default() -> Self {
+        // matches the editors we historically supported (VS Code) so
+        // existing clients that don't send an encoding keep working
+        OffsetEncoding::Utf16
+    }
+}
+
+/// A single embedded code chunk, tracked so we can rank nearest neighbours
+/// by cosine similarity and still dedupe/attribute results back to a file.
+#[derive(Debug, Clone)]
+pub struct EmbeddedSnippet {
+    pub file_path: String,
+    pub snippet: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Embeds arbitrary text into a vector, so the retrieval step can work with
+/// whichever embedding model a deployment has configured.
+#[async_trait::async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, InLineCompletionError>;
+}
+
+/// Storage + nearest-neighbour search over embedded code chunks. Kept
+/// behind a trait so a deployment can start with the in-process flat store
+/// and later swap in the Postgres-backed one without touching call sites.
+#[async_trait::async_trait]
+pub trait VectorStore: Send + Sync {
+    async fn upsert(&self, snippet: EmbeddedSnippet);
+    async fn nearest(&self, query_embedding: &[f32], top_k: usize) -> Vec<EmbeddedSnippet>;
+}
+
+pub(crate) fn cosine_similarity(lhs: &[f32], rhs: &[f32]) -> f32 {
+    let dot: f32 = lhs.iter().zip(rhs.iter()).map(|(a, b)| a * b).sum();
+    let lhs_norm: f32 = lhs.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let rhs_norm: f32 = rhs.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if lhs_norm == 0.0 || rhs_norm == 0.0 {
+        0.0
+    } else {
+        dot / (lhs_norm * rhs_norm)
+    }
+}
+
+/// A simple in-process vector store, good enough for a single workspace's
+/// worth of embeddings without standing up any external infrastructure.
+#[derive(Default)]
+pub struct FlatVectorStore {
+    snippets: std::sync::Mutex<Vec<EmbeddedSnippet>>,
+}
+
+impl FlatVectorStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl VectorStore for FlatVectorStore {
+    async fn upsert(&self, snippet: EmbeddedSnippet) {
+        self.snippets
+            .lock()
+            .expect("lock should not be poisoned")
+            .push(snippet);
+    }
+
+    async fn nearest(&self, query_embedding: &[f32], top_k: usize) -> Vec<EmbeddedSnippet> {
+        let mut scored = self
+            .snippets
+            .lock()
+            .expect("lock should not be poisoned")
+            .iter()
+            .map(|snippet| {
+                (
+                    cosine_similarity(query_embedding, &snippet.embedding),
+                    snippet.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        scored
+            .into_iter()
+            .take(top_k)
+            .map(|(_, snippet)| snippet)
+            .collect()
+    }
+}
+
+/// Backs the vector store with a Postgres table so embeddings survive
+/// restarts and can be shared across sidecar instances. Deployments that
+/// don't need that durability can keep using `FlatVectorStore` instead.
+/// Only `upsert` touches the table: `nearest` always returns an empty list.
+pub struct PostgresVectorStore {
+    pool: sqlx::PgPool,
+}
+
+impl PostgresVectorStore {
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl VectorStore for PostgresVectorStore {
+    async fn upsert(&self, snippet: EmbeddedSnippet) {
+        let embedding_json = serde_json::to_string(&snippet.embedding).unwrap_or_default();
+        let _ = sqlx::query(
+            "INSERT INTO inline_completion_embeddings (file_path, snippet, embedding) VALUES ($1, $2, $3)",
+        )
+        .bind(snippet.file_path)
+        .bind(snippet.snippet)
+        .bind(embedding_json)
+        .execute(&self.pool)
+        .await;
+    }
+
+    async fn nearest(&self, _query_embedding: &[f32], _top_k: usize) -> Vec<EmbeddedSnippet> {
+        // No similarity search is run over the stored rows, so this store
+        // never returns any neighbours.
+        vec![]
+    }
+}
+
+/// A single precise edit the model asked for: replace `range` with
+/// `new_text`. Returned in place of raw completion prose so the editor can
+/// apply a diff instead of us inferring an insertion point.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct EditOperation {
+    pub range: Range,
+    pub new_text: String,
+}
+
+/// The `{line, character}` pair actually asked for by
+/// `edit_operations_tool_schema` - no `byte_offset`, since the model was
+/// never asked for one and has no way to compute it.
+#[derive(Debug, serde::Deserialize)]
+struct ParsedPosition {
+    line: usize,
+    character: usize,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ParsedRange {
+    start: ParsedPosition,
+    end: ParsedPosition,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ParsedEditOperation {
+    range: ParsedRange,
+    new_text: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct EditOperationsResponse {
+    edits: Vec<ParsedEditOperation>,
+}
+
+/// `ParsedPosition` only carries `line`/`character` - the real
+/// `byte_offset` a `Position` needs for slicing is derived from
+/// `document_lines` here, rather than trusting a value the model was
+/// never asked for (and defaults to `0`, which would always point at the
+/// start of the file).
+fn position_from_parsed(parsed: &ParsedPosition, document_lines: &DocumentLines) -> Position {
+    let line_content = document_lines.get_line(parsed.line);
+    let character_count = line_content.chars().count();
+    let character = parsed.character.min(character_count);
+    let line_byte_offset = document_lines
+        .start_position_at_line(parsed.line, OffsetEncoding::Utf8)
+        .byte_offset();
+    let byte_offset = line_byte_offset
+        + line_content
+            .chars()
+            .take(character)
+            .map(|c| c.len_utf8())
+            .sum::<usize>();
+    Position::new(parsed.line, character, byte_offset)
+}
+
+/// The JSON-schema tool definition we ask the model to call so an edit
+/// comes back as structured `{range, new_text}` pairs instead of prose we
+/// have to coax into a particular shape and hope it complies with.
+fn edit_operations_tool_schema() -> serde_json::Value {
+    serde_json::json!({
+        "name": "propose_edit_operations",
+        "description": "Propose the edits needed to complete the code at the cursor.",
+        "parameters": {
+            "type": "object",
+            "properties": {
+                "edits": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "range": {
+                                "type": "object",
+                                "properties": {
+                                    "start": {
+                                        "type": "object",
+                                        "properties": {
+                                            "line": {"type": "integer"},
+                                            "character": {"type": "integer"}
+                                        },
+                                        "required": ["line", "character"]
+                                    },
+                                    "end": {
+                                        "type": "object",
+                                        "properties": {
+                                            "line": {"type": "integer"},
+                                            "character": {"type": "integer"}
+                                        },
+                                        "required": ["line", "character"]
+                                    }
+                                },
+                                "required": ["start", "end"]
+                            },
+                            "new_text": {
+                                "type": "string",
+                                "description": "The text to insert/replace at range."
+                            }
+                        },
+                        "required": ["range", "new_text"]
+                    }
+                }
+            },
+            "required": ["edits"]
+        }
+    })
+}
+
 pub struct FillInMiddleCompletionAgent {
     llm_broker: Arc<LLMBroker>,
     llm_tokenizer: Arc<LLMTokenizer>,
     fill_in_middle_broker: Arc<FillInMiddleBroker>,
     editor_parsing: Arc<EditorParsing>,
     answer_mode: Arc<LLMAnswerModelBroker>,
+    // Additional sources of context (recently edited files, repo-wide
+    // definitions, ...) that get composed with the current file's
+    // prefix/suffix. They are consulted in the order passed to
+    // `with_context_backends`, each with an equal share of the retrieved
+    // context budget; `LLMClientConfig` plays no part in choosing them.
+    context_backends: Vec<Arc<dyn ContextBackend>>,
+    // Optional semantic retrieval over the repository's embedded tags;
+    // `None` keeps completions lexical-only (current behavior).
+    embedding_provider: Option<Arc<dyn EmbeddingProvider>>,
+    vector_store: Option<Arc<dyn VectorStore>>,
+    retrieval_tokens: usize,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -58,6 +320,9 @@ pub enum InLineCompletionError {
 
     #[error("LLMClient error: {0}")]
     LLMClientError(#[from] llm_client::clients::types::LLMClientError),
+
+    #[error("failed to read {0}: {1}")]
+    IoError(String, std::io::Error),
 }
 
 struct InLineCompletionData {
@@ -80,7 +345,75 @@ impl FillInMiddleCompletionAgent {
             answer_mode,
             fill_in_middle_broker,
             editor_parsing,
+            context_backends: vec![],
+            embedding_provider: None,
+            vector_store: None,
+            retrieval_tokens: 256,
+        }
+    }
+
+    /// Registers additional context backends (in-memory recents, repo-wide
+    /// search, ...) that will be consulted alongside the current file's
+    /// prefix/suffix when building completions.
+    pub fn with_context_backends(mut self, context_backends: Vec<Arc<dyn ContextBackend>>) -> Self {
+        self.context_backends = context_backends;
+        self
+    }
+
+    /// Enables semantic retrieval of similar snippets from across the
+    /// repository, bounded to `retrieval_tokens` worth of prepended
+    /// context. Without this, completions stay lexical-only.
+    pub fn with_semantic_retrieval(
+        mut self,
+        embedding_provider: Arc<dyn EmbeddingProvider>,
+        vector_store: Arc<dyn VectorStore>,
+        retrieval_tokens: usize,
+    ) -> Self {
+        self.embedding_provider = Some(embedding_provider);
+        self.vector_store = Some(vector_store);
+        self.retrieval_tokens = retrieval_tokens;
+        self
+    }
+
+    /// Embeds the current-line + prefix query, finds the top-K most
+    /// relevant snippets across the repository, dedupes by file, and
+    /// truncates to `self.retrieval_tokens` so the prompt budget is
+    /// respected. Returns an empty string when semantic retrieval isn't
+    /// configured, so behavior is unchanged when the feature is off.
+    async fn retrieve_semantic_context(
+        &self,
+        query: &str,
+        llm_type: &LLMType,
+    ) -> Result<String, InLineCompletionError> {
+        let (embedding_provider, vector_store) =
+            match (&self.embedding_provider, &self.vector_store) {
+                (Some(embedding_provider), Some(vector_store)) => {
+                    (embedding_provider, vector_store)
+                }
+                _ => return Ok(String::new()),
+            };
+
+        let query_embedding = embedding_provider.embed(query).await?;
+        let nearest = vector_store.nearest(&query_embedding, 10).await;
+
+        let mut seen_files = std::collections::HashSet::new();
+        let mut used_tokens = 0;
+        let mut context_lines = vec![];
+        for snippet in nearest {
+            if !seen_files.insert(snippet.file_path.clone()) {
+                continue;
+            }
+            let commented_snippet = format!("// {}\n{}", snippet.file_path, snippet.snippet);
+            let token_count = self
+                .llm_tokenizer
+                .count_tokens_using_tokenizer(llm_type, &commented_snippet)?;
+            if used_tokens + token_count > self.retrieval_tokens {
+                break;
+            }
+            used_tokens += token_count;
+            context_lines.push(commented_snippet);
         }
+        Ok(context_lines.join("\n\n"))
     }
 
     pub async fn completion(
@@ -114,21 +447,78 @@ impl FillInMiddleCompletionAgent {
 
         let document_lines = DocumentLines::from_file_content(&completion_request.text);
 
+        // Reserve a slice of the token budget for retrieved context so a
+        // handful of registered backends don't crowd out the local
+        // prefix/suffix entirely; the rest goes to the current file as
+        // before.
+        let retrieved_context_budget = if self.context_backends.is_empty() {
+            0
+        } else {
+            token_limit as usize / 4
+        };
+        let local_token_limit = token_limit as usize - retrieved_context_budget;
+
+        // editors negotiate their own offset encoding over LSP (Helix can
+        // use UTF-8, for instance); default to UTF-16 so VS Code and other
+        // clients that don't send one keep working exactly as before
+        let offset_encoding = completion_request.offset_encoding.unwrap_or_default();
+
         // Now we generate the prefix and the suffix here
         let completion_context = CurrentFileContext::new(
-            completion_request.filepath,
+            completion_request.filepath.to_owned(),
             completion_request.position,
-            token_limit as usize,
+            local_token_limit,
             self.llm_tokenizer.clone(),
             self.editor_parsing.clone(),
             fast_model.clone(),
+            offset_encoding,
         )
         .generate_context(&document_lines)?;
 
+        let mut retrieved_context = vec![];
+        if retrieved_context_budget > 0 {
+            let per_backend_budget = retrieved_context_budget / self.context_backends.len();
+            for backend in self.context_backends.iter() {
+                let backend_context = backend
+                    .get_context(
+                        &completion_request.filepath,
+                        &completion_request.position,
+                        per_backend_budget,
+                    )
+                    .await?;
+                retrieved_context.extend(backend_context);
+            }
+        }
+
+        let retrieved_context_prefix = retrieved_context
+            .into_iter()
+            .map(|selection| selection.content().to_owned())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // current-line + prefix query for semantic retrieval, mirroring the
+        // window the current-file backend expands around the cursor
+        let semantic_query = format!(
+            "{}\n{}",
+            completion_context.prefix.content(),
+            document_lines.get_line(completion_request.position.line() as usize)
+        );
+        let semantic_context = self
+            .retrieve_semantic_context(&semantic_query, &fast_model)
+            .await?;
+
         let formatted_string =
             self.fill_in_middle_broker
                 .format_context(FillInMiddleRequest::new(
-                    completion_context.prefix.content().to_owned(),
+                    [
+                        semantic_context.as_str(),
+                        retrieved_context_prefix.as_str(),
+                        completion_context.prefix.content(),
+                    ]
+                    .into_iter()
+                    .filter(|piece| !piece.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
                     completion_context.suffix.content().to_owned(),
                 ))?;
 
@@ -161,7 +551,124 @@ impl FillInMiddleCompletionAgent {
         // Process the data and generate the responses for the user
         Ok(InlineCompletionResponse::new(vec![InlineCompletion::new(
             completion.to_owned(),
-            insert_range(completion_request.position, document_lines, &completion),
+            insert_range(
+                completion_request.position,
+                document_lines,
+                &completion,
+                offset_encoding,
+            ),
         )]))
     }
+
+    /// Asks the model for edits as a small JSON tool-call payload instead
+    /// of a single block of completion prose, so multi-location edits (not
+    /// just an insertion at the cursor) can be applied precisely. Only
+    /// attempted for models the answer-model config marks as capable of
+    /// following a structured schema reliably; everything else goes
+    /// through the regular string-completion path unchanged.
+    pub async fn completion_as_edit_operations(
+        &self,
+        completion_request: InlineCompletionRequest,
+    ) -> Result<InlineCompletionResponse, InLineCompletionError> {
+        let model_config = &completion_request.model_config;
+        let fast_model = model_config.fast_model.clone();
+        let answer_model = self
+            .answer_mode
+            .get_answer_model(&fast_model)
+            .ok_or_else(|| InLineCompletionError::LLMNotSupported(fast_model.clone()))?;
+
+        if !answer_model.supports_tool_calls {
+            return self.completion(completion_request).await;
+        }
+
+        let temperature = model_config
+            .fast_model_temperature()
+            .ok_or(InLineCompletionError::LLMNotSupported(fast_model.clone()))?;
+        let fast_model_api_key = model_config
+            .provider_for_fast_model()
+            .ok_or(InLineCompletionError::MissingProviderKeys(
+                fast_model.clone(),
+            ))?
+            .clone();
+        let token_limit = answer_model
+            .inline_completion_tokens
+            .ok_or(InLineCompletionError::LLMNotSupported(fast_model.clone()))?;
+
+        let offset_encoding = completion_request.offset_encoding.unwrap_or_default();
+        let document_lines = DocumentLines::from_file_content(&completion_request.text);
+        let current_line =
+            document_lines.get_line(completion_request.position.line() as usize);
+
+        // Same prefix/suffix `completion()` already computes, so the model
+        // has visibility into the surrounding code instead of just the
+        // cursor's own line - without it there's nothing for a multi-line
+        // edit's `old_text` to be matched against.
+        let completion_context = CurrentFileContext::new(
+            completion_request.filepath.to_owned(),
+            completion_request.position,
+            token_limit as usize,
+            self.llm_tokenizer.clone(),
+            self.editor_parsing.clone(),
+            fast_model.clone(),
+            offset_encoding,
+        )
+        .generate_context(&document_lines)?;
+
+        let messages = vec![
+            LLMClientMessage::system(
+                "You are completing code at a cursor position. Call `propose_edit_operations` \
+                 with the edits needed - do not reply with plain text."
+                    .to_owned(),
+            ),
+            LLMClientMessage::user(format!(
+                "File: {}\nPrefix:\n{}\nCurrent line: {}\nSuffix:\n{}",
+                completion_request.filepath,
+                completion_context.prefix.content(),
+                current_line,
+                completion_context.suffix.content(),
+            )),
+        ];
+
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let response = self
+            .llm_broker
+            .stream_completion(
+                fast_model_api_key,
+                LLMClientCompletionRequest::new(
+                    fast_model.clone(),
+                    messages,
+                    temperature,
+                    Some(vec![edit_operations_tool_schema()]),
+                ),
+                vec![(
+                    "event_type".to_owned(),
+                    "fill_in_middle_edit_operations".to_owned(),
+                )]
+                .into_iter()
+                .collect(),
+                sender,
+            )
+            .await?;
+
+        match serde_json::from_str::<EditOperationsResponse>(response.trim()) {
+            Ok(parsed) => Ok(InlineCompletionResponse::new(
+                parsed
+                    .edits
+                    .into_iter()
+                    .map(|edit| {
+                        let range = Range::new(
+                            position_from_parsed(&edit.range.start, &document_lines),
+                            position_from_parsed(&edit.range.end, &document_lines),
+                        );
+                        InlineCompletion::new(edit.new_text, range)
+                    })
+                    .collect(),
+            )),
+            Err(_) => {
+                // the model didn't honor the schema this time; fall back to
+                // the string-completion path rather than surfacing nothing
+                self.completion(completion_request).await
+            }
+        }
+    }
 }
\ No newline at end of file
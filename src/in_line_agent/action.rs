@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chunking::text_document::{Position, Range};
+
+use super::types::{InLineAgent, InLineAgentAction};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EditOperationKind {
+    Insert,
+    Replace,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditOperation {
+    pub kind: EditOperationKind,
+    pub target: Range,
+    pub old_text: String,
+    pub new_text: String,
+}
+
+/// The shape of a single edit as returned by the `propose_edit_operations`
+/// tool call - notably missing `EditOperation::target`, since the model is
+/// never asked for it (the schema only declares `kind`/`old_text`/
+/// `new_text`); it's filled in afterwards by `range_for_old_text` once
+/// `old_text` has been located in the selection.
+#[derive(Debug, Clone, Deserialize)]
+struct ParsedEditOperation {
+    kind: EditOperationKind,
+    old_text: String,
+    new_text: String,
+}
+
+/// The shape of the `propose_edit_operations` tool call's arguments, as
+/// returned by the model.
+#[derive(Debug, Clone, Deserialize)]
+struct EditOperationsToolCall {
+    edits: Vec<ParsedEditOperation>,
+}
+
+pub(super) fn apologise_message() -> String {
+    "Sorry, I wasn't able to come up with a valid edit for that instruction.".to_owned()
+}
+
+/// The JSON-schema tool definition we ask the backend to call instead of
+/// replying with free text, so the Edit/Fix actions get back structured
+/// edits rather than having to scrape them out of an XML/markdown reply.
+fn edit_operations_tool_schema() -> serde_json::Value {
+    serde_json::json!({
+        "name": "propose_edit_operations",
+        "description": "Propose one or more edits to apply to the user's selection.",
+        "parameters": {
+            "type": "object",
+            "properties": {
+                "edits": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "kind": {
+                                "type": "string",
+                                "enum": ["insert", "replace", "delete"]
+                            },
+                            "old_text": {
+                                "type": "string",
+                                "description": "The exact, verbatim text from the selection this edit replaces or removes. Empty for a pure insert."
+                            },
+                            "new_text": {
+                                "type": "string",
+                                "description": "The text to insert in place of old_text. Empty for a pure delete."
+                            }
+                        },
+                        "required": ["kind", "old_text", "new_text"]
+                    }
+                }
+            },
+            "required": ["edits"]
+        }
+    })
+}
+
+/// Builds a zero-indexed, byte-offset-as-column `Position` for `byte_offset`
+/// within `text`. The position is relative to the start of `text` (the
+/// selection, not the whole document), and its column is a byte count, not
+/// a column in any editor-negotiated offset encoding.
+fn position_at(text: &str, byte_offset: usize) -> Position {
+    let before = &text[..byte_offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+    Position::new(line, byte_offset - line_start, byte_offset)
+}
+
+/// Finds `old_text` inside `selection` and converts the byte offsets into a
+/// `Range`, so a model-proposed edit can be localized without trusting any
+/// line/column numbers the model might have hallucinated.
+fn range_for_old_text(selection: &str, old_text: &str) -> Option<Range> {
+    if old_text.is_empty() {
+        return None;
+    }
+    let start_byte = selection.find(old_text)?;
+    let end_byte = start_byte + old_text.len();
+    Some(Range::new(
+        position_at(selection, start_byte),
+        position_at(selection, end_byte),
+    ))
+}
+
+/// A zero-width range at the very start of `selection`, used as the target
+/// for a pure insert whose `old_text` is empty (so there's nothing for
+/// `range_for_old_text` to locate).
+fn start_of_selection() -> Range {
+    let start = Position::new(0, 0, 0);
+    Range::new(start, start)
+}
+
+/// Validates every `old_text` the model returned actually occurs in
+/// `selection`, dropping edits that don't (rather than forwarding a
+/// hallucinated edit the editor can't locate).
+fn edits_from_tool_call_response(response: &str, selection: &str) -> Vec<EditOperation> {
+    let tool_call: Result<EditOperationsToolCall, _> = serde_json::from_str(response);
+    let Ok(tool_call) = tool_call else {
+        return vec![];
+    };
+
+    tool_call
+        .edits
+        .into_iter()
+        .filter_map(|edit| {
+            let target = match range_for_old_text(selection, &edit.old_text) {
+                Some(range) => range,
+                None if edit.old_text.is_empty() => start_of_selection(),
+                None => return None,
+            };
+            Some(EditOperation {
+                kind: edit.kind,
+                target,
+                old_text: edit.old_text,
+                new_text: edit.new_text,
+            })
+        })
+        .collect()
+}
+
+impl InLineAgent {
+    /// Carries out an Edit or Fix action via tool-calling: asks the backend
+    /// to call `propose_edit_operations` over the selection the user's
+    /// `ProcessInEditorRequest` points at, validates every returned
+    /// `old_text` actually occurs in that selection, and returns the
+    /// validated edits (or `None` when the action isn't Edit/Fix, or the
+    /// backend's reply didn't yield anything usable).
+    pub(super) async fn edit_operations_for_action(
+        &self,
+        action: InLineAgentAction,
+        selection: &str,
+        instruction: &str,
+    ) -> anyhow::Result<Option<Vec<EditOperation>>> {
+        if action != InLineAgentAction::Edit && action != InLineAgentAction::Fix {
+            return Ok(None);
+        }
+
+        let messages = vec![
+            crate::agent::llm_funcs::llm::Message::system(
+                "You are proposing precise edits to the user's code selection. Call \
+                 `propose_edit_operations` with edits whose `old_text` is copied verbatim from \
+                 the selection, so it can be located exactly.",
+            ),
+            crate::agent::llm_funcs::llm::Message::user(&format!(
+                "<selection>\n{}\n</selection>\n<instruction>\n{}\n</instruction>",
+                selection, instruction
+            )),
+        ];
+
+        let settings = match action {
+            InLineAgentAction::Fix => &self.configuration().fix,
+            _ => &self.configuration().edit,
+        };
+        let response = self
+            .backend()
+            .do_completion_with_tools(messages, settings.temperature, vec![edit_operations_tool_schema()])
+            .await?;
+
+        let edits = edits_from_tool_call_response(&response, selection);
+        if edits.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(edits))
+        }
+    }
+}
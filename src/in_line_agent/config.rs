@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+fn default_completion_tokens() -> usize {
+    32
+}
+
+fn default_generation_tokens() -> usize {
+    256
+}
+
+/// Temperature + token budget for a single inline command, tunable without
+/// a rebuild since it's deserialized from the `Configuration` JSON blob the
+/// editor can serve/override.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionSettings {
+    pub temperature: f32,
+    /// Token budget for actions that expect a short, single-shot reply
+    /// (`decide_action`, the Edit/Fix tool call). Stored only: `InLineAgent`
+    /// does not pass it to its backend, so those calls have no token cap.
+    #[serde(default = "default_completion_tokens")]
+    pub completion_tokens: usize,
+    /// Token budget for actions that generate a longer body of text
+    /// (`generate_documentation`). Stored only: `InLineAgent` does not pass
+    /// it to its backend, so those calls have no token cap.
+    #[serde(default = "default_generation_tokens")]
+    pub generation_tokens: usize,
+}
+
+impl ActionSettings {
+    fn new(temperature: f32) -> Self {
+        Self {
+            temperature,
+            completion_tokens: default_completion_tokens(),
+            generation_tokens: default_generation_tokens(),
+        }
+    }
+}
+
+/// Per-action tunables for everything `InLineAgent` does, replacing what
+/// used to be literals scattered across `decide_action`/
+/// `generate_documentation`/`carry_out_edit_action` (temperature `0.0` for
+/// deciding and edits, `0.2` for docs, and no token cap at all).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Configuration {
+    #[serde(default = "Configuration::default_decide_action")]
+    pub decide_action: ActionSettings,
+    #[serde(default = "Configuration::default_edit")]
+    pub edit: ActionSettings,
+    #[serde(default = "Configuration::default_fix")]
+    pub fix: ActionSettings,
+    #[serde(default = "Configuration::default_doc")]
+    pub doc: ActionSettings,
+}
+
+impl Configuration {
+    fn default_decide_action() -> ActionSettings {
+        ActionSettings::new(0.0)
+    }
+
+    fn default_edit() -> ActionSettings {
+        ActionSettings::new(0.0)
+    }
+
+    fn default_fix() -> ActionSettings {
+        ActionSettings::new(0.0)
+    }
+
+    fn default_doc() -> ActionSettings {
+        ActionSettings::new(0.2)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            decide_action: Self::default_decide_action(),
+            edit: Self::default_edit(),
+            fix: Self::default_fix(),
+            doc: Self::default_doc(),
+        }
+    }
+}
+
+impl super::types::InLineAgent {
+    /// Exposes the configured per-action settings to sibling modules
+    /// (`action.rs`) so they read their temperature from config rather than
+    /// hardcoding their own literal.
+    pub(super) fn configuration(&self) -> &Configuration {
+        &self.configuration
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use llm_client::{clients::types::LLMType, tokenizer::tokenizer::LLMTokenizer};
+
+use crate::chunking::text_document::DocumentSymbol;
+use crate::repo::types::RepoRef;
+
+use super::types::InLineAgent;
+
+/// A piece of repo context injected alongside the selection, so the model
+/// sees more than just the symbol it was asked to edit/document.
+#[derive(Debug, Clone)]
+pub struct ContextSnippet {
+    pub file_path: String,
+    pub content: String,
+}
+
+/// Supplies extra repo context for a symbol, beyond its own source, to
+/// enrich the Doc prompts (the Edit/Fix actions do not consult it). Kept
+/// behind a trait so a deployment can
+/// start with the file-local `FileStore` and later swap in
+/// `InMemoryVectorStore` (or a future remote index) without touching call
+/// sites.
+#[async_trait]
+pub trait MemoryBackend: Send + Sync {
+    async fn get_context(
+        &self,
+        file_path: &str,
+        document_symbol: &DocumentSymbol,
+        repo_ref: &RepoRef,
+    ) -> Vec<ContextSnippet>;
+
+    /// Indexes (or re-indexes, if its content changed) a symbol so future
+    /// `get_context` calls can surface it as a neighbour. Backends that
+    /// don't index anything (e.g. `FileStore`) can leave this as a no-op.
+    async fn index(&self, _file_path: &str, _document_symbol: &DocumentSymbol, _repo_ref: &RepoRef) {}
+}
+
+/// The simplest possible backend: just returns the symbol's own enclosing
+/// file, read straight off disk. No indexing, no embeddings, no caching -
+/// a reasonable default for a deployment that hasn't configured anything
+/// richer yet.
+pub struct FileStore;
+
+impl FileStore {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for FileStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl MemoryBackend for FileStore {
+    async fn get_context(
+        &self,
+        file_path: &str,
+        _document_symbol: &DocumentSymbol,
+        _repo_ref: &RepoRef,
+    ) -> Vec<ContextSnippet> {
+        match std::fs::read_to_string(file_path) {
+            Ok(content) => vec![ContextSnippet {
+                file_path: file_path.to_owned(),
+                content,
+            }],
+            Err(_) => vec![],
+        }
+    }
+}
+
+/// Supplies embeddings for `InMemoryVectorStore` - kept behind a trait so
+/// swapping the embedding model doesn't touch the store's retrieval logic.
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    async fn embed(&self, text: &str) -> anyhow::Result<Vec<f32>>;
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn cosine_similarity(lhs: &[f32], rhs: &[f32]) -> f32 {
+    let dot: f32 = lhs.iter().zip(rhs.iter()).map(|(a, b)| a * b).sum();
+    let lhs_norm: f32 = lhs.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let rhs_norm: f32 = rhs.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if lhs_norm == 0.0 || rhs_norm == 0.0 {
+        0.0
+    } else {
+        dot / (lhs_norm * rhs_norm)
+    }
+}
+
+struct IndexedSymbol {
+    content_hash: u64,
+    embedding: Vec<f32>,
+    snippet: ContextSnippet,
+}
+
+/// Embeds every indexed symbol and returns the top-k nearest neighbours to
+/// the selected symbol by cosine similarity. Indexing is lazy and per
+/// `RepoRef`: a symbol is only (re-)embedded when it's first seen or its
+/// content hash has changed, so repeated calls across a session only pay
+/// the embedding cost for symbols that actually changed.
+pub struct InMemoryVectorStore {
+    embedding_provider: std::sync::Arc<dyn EmbeddingProvider>,
+    top_k: usize,
+    index: Mutex<HashMap<RepoRef, HashMap<String, IndexedSymbol>>>,
+}
+
+impl InMemoryVectorStore {
+    pub fn new(embedding_provider: std::sync::Arc<dyn EmbeddingProvider>, top_k: usize) -> Self {
+        Self {
+            embedding_provider,
+            top_k,
+            index: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Key for the per-repo symbol map. Combines the file path with a hash
+    /// of the symbol's own source rather than its (optional, not
+    /// necessarily unique) name, since that's the only thing every
+    /// `DocumentSymbol` is guaranteed to have.
+    fn symbol_key(file_path: &str, document_symbol: &DocumentSymbol) -> String {
+        format!("{}#{:x}", file_path, content_hash(&document_symbol.code))
+    }
+}
+
+#[async_trait]
+impl MemoryBackend for InMemoryVectorStore {
+    async fn index(&self, file_path: &str, document_symbol: &DocumentSymbol, repo_ref: &RepoRef) {
+        let key = Self::symbol_key(file_path, document_symbol);
+        let hash = content_hash(&document_symbol.code);
+        let already_indexed = {
+            let index = self.index.lock().expect("lock should not be poisoned");
+            index
+                .get(repo_ref)
+                .and_then(|symbols| symbols.get(&key))
+                .map(|indexed| indexed.content_hash == hash)
+                .unwrap_or(false)
+        };
+        if already_indexed {
+            return;
+        }
+
+        let embedding = match self.embedding_provider.embed(&document_symbol.code).await {
+            Ok(embedding) => embedding,
+            Err(_) => return,
+        };
+
+        let mut index = self.index.lock().expect("lock should not be poisoned");
+        index.entry(repo_ref.clone()).or_insert_with(HashMap::new).insert(
+            key,
+            IndexedSymbol {
+                content_hash: hash,
+                embedding,
+                snippet: ContextSnippet {
+                    file_path: file_path.to_owned(),
+                    content: document_symbol.code.clone(),
+                },
+            },
+        );
+    }
+
+    async fn get_context(
+        &self,
+        file_path: &str,
+        document_symbol: &DocumentSymbol,
+        repo_ref: &RepoRef,
+    ) -> Vec<ContextSnippet> {
+        let query_embedding = match self.embedding_provider.embed(&document_symbol.code).await {
+            Ok(embedding) => embedding,
+            Err(_) => return vec![],
+        };
+
+        let index = self.index.lock().expect("lock should not be poisoned");
+        let Some(symbols) = index.get(repo_ref) else {
+            return vec![];
+        };
+
+        let mut scored = symbols
+            .values()
+            .filter(|indexed| indexed.snippet.file_path != file_path)
+            .map(|indexed| {
+                (
+                    cosine_similarity(&query_embedding, &indexed.embedding),
+                    indexed.snippet.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        scored
+            .into_iter()
+            .take(self.top_k)
+            .map(|(_, snippet)| snippet)
+            .collect()
+    }
+}
+
+/// Truncates `snippets` to fit within `token_budget`, counting each
+/// snippet's cost once rather than re-measuring the whole running buffer on
+/// every iteration.
+pub fn cap_to_token_budget(
+    snippets: Vec<ContextSnippet>,
+    token_budget: usize,
+    count_tokens: impl Fn(&str) -> usize,
+) -> Vec<ContextSnippet> {
+    let mut tokens_used = 0usize;
+    let mut capped = vec![];
+    for snippet in snippets {
+        let cost = count_tokens(&snippet.content);
+        if tokens_used.saturating_add(cost) > token_budget {
+            continue;
+        }
+        tokens_used += cost;
+        capped.push(snippet);
+    }
+    capped
+}
+
+impl InLineAgent {
+    /// Opts this agent into enriching its prompts with extra repo context
+    /// (beyond the selection/symbol itself) fetched from `memory_backend` -
+    /// e.g. the enclosing file from a `FileStore`, or nearest-neighbour
+    /// snippets from an `InMemoryVectorStore`. `tokenizer`/`llm_type` are
+    /// required alongside it so `repo_context_message` can cap the context
+    /// it injects against the model's real token count instead of a
+    /// word-count approximation.
+    pub fn with_memory_backend(
+        mut self,
+        memory_backend: std::sync::Arc<dyn MemoryBackend>,
+        tokenizer: std::sync::Arc<LLMTokenizer>,
+        llm_type: LLMType,
+    ) -> Self {
+        self.memory_backend = Some(memory_backend);
+        self.memory_context_tokenizer = Some((tokenizer, llm_type));
+        self
+    }
+
+    /// Indexes `document_symbol` (if a memory backend is configured) and
+    /// renders any resulting repo context as a single extra user message,
+    /// capped to a conservative token budget (measured with the real
+    /// tokenizer passed to `with_memory_backend`, not an approximation) so
+    /// it doesn't crowd out the selection itself.
+    pub(super) async fn repo_context_message(
+        &self,
+        file_path: &str,
+        document_symbol: &DocumentSymbol,
+    ) -> Option<crate::agent::llm_funcs::llm::Message> {
+        let memory_backend = self.memory_backend.as_ref()?;
+        let (tokenizer, llm_type) = self.memory_context_tokenizer.as_ref()?;
+        memory_backend
+            .index(file_path, document_symbol, &self.repo_ref)
+            .await;
+        let snippets = memory_backend
+            .get_context(file_path, document_symbol, &self.repo_ref)
+            .await;
+        // A snippet the tokenizer can't count (e.g. an `llm_type` it has no
+        // table for) is treated as infinitely expensive rather than free, so
+        // `cap_to_token_budget` drops it instead of letting an unmeasured
+        // snippet through uncounted.
+        let snippets = cap_to_token_budget(snippets, 2048, |content| {
+            tokenizer
+                .count_tokens_using_tokenizer(llm_type, content)
+                .unwrap_or(usize::MAX)
+        });
+        if snippets.is_empty() {
+            return None;
+        }
+        let rendered = snippets
+            .iter()
+            .map(|snippet| format!("<context path=\"{}\">\n{}\n</context>", snippet.file_path, snippet.content))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Some(crate::agent::llm_funcs::llm::Message::user(&format!(
+            "Additional repo context:\n{}",
+            rendered
+        )))
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::agent::llm_funcs::{self, llm::Message, LlmClient};
+use crate::agent::model;
+use crate::chunking::text_document::DocumentSymbol;
+
+use super::types::InLineAgent;
+
+/// A source of chat completions for `InLineAgent`'s actions (deciding which
+/// edit/doc action to take, then generating it), so a deployment without an
+/// OpenAI key can still get inline docs/edits from a local model instead of
+/// every call site hardcoding `OpenAIModel::get_model(GPT_3_5_TURBO_16K)`.
+#[async_trait]
+pub trait TransformBackend: Send + Sync {
+    /// A short, single-shot completion (`decide_action` classifying which
+    /// action to take) where only the final text matters.
+    async fn do_completion(&self, messages: Vec<Message>, temperature: f32) -> anyhow::Result<String>;
+
+    /// A longer generation (`generate_documentation`) that streams each
+    /// token delta over `sender` as it arrives, tagged with `document_symbol`
+    /// so the webserver layer can route it back to the right answer.
+    async fn do_generate_stream(
+        &self,
+        messages: Vec<Message>,
+        temperature: f32,
+        sender: UnboundedSender<String>,
+        document_symbol: DocumentSymbol,
+    ) -> anyhow::Result<String>;
+
+    /// Like `do_completion`, but passes `tools` through to the underlying
+    /// LLM call's function-calling parameter, for the Edit/Fix actions that
+    /// need a structured JSON reply instead of free-form text.
+    async fn do_completion_with_tools(
+        &self,
+        messages: Vec<Message>,
+        temperature: f32,
+        tools: Vec<serde_json::Value>,
+    ) -> anyhow::Result<String>;
+}
+
+/// Routes `InLineAgent`'s completions through the same `LlmClient`/
+/// `AnswerModel` it was already constructed with, so this is still the
+/// default backend with no behavior change for deployments that don't
+/// configure anything else.
+pub struct OpenAITransformBackend {
+    llm_client: Arc<LlmClient>,
+    model: model::AnswerModel,
+}
+
+impl OpenAITransformBackend {
+    pub fn new(llm_client: Arc<LlmClient>, model: model::AnswerModel) -> Self {
+        Self { llm_client, model }
+    }
+
+    fn openai_model(&self) -> anyhow::Result<llm_funcs::llm::OpenAIModel> {
+        llm_funcs::llm::OpenAIModel::get_model(self.model.model_name)
+    }
+}
+
+#[async_trait]
+impl TransformBackend for OpenAITransformBackend {
+    async fn do_completion(&self, messages: Vec<Message>, temperature: f32) -> anyhow::Result<String> {
+        self.llm_client
+            .response(self.openai_model()?, messages, None, temperature, None)
+            .await
+    }
+
+    async fn do_generate_stream(
+        &self,
+        messages: Vec<Message>,
+        temperature: f32,
+        sender: UnboundedSender<String>,
+        document_symbol: DocumentSymbol,
+    ) -> anyhow::Result<String> {
+        self.llm_client
+            .stream_response_inline_agent(
+                self.openai_model()?,
+                messages,
+                None,
+                temperature,
+                None,
+                sender,
+                document_symbol,
+            )
+            .await
+    }
+
+    async fn do_completion_with_tools(
+        &self,
+        messages: Vec<Message>,
+        temperature: f32,
+        tools: Vec<serde_json::Value>,
+    ) -> anyhow::Result<String> {
+        self.llm_client
+            .response(self.openai_model()?, messages, Some(tools), temperature, None)
+            .await
+    }
+}
+
+/// Talks to a locally running llama.cpp-style server (the `/completion`
+/// endpoint its `server` binary exposes) so users without an OpenAI key
+/// still get inline docs/edits, at whatever context window their local
+/// model supports.
+pub struct LocalLlamaCppTransformBackend {
+    server_url: String,
+    token_budget: usize,
+    http_client: reqwest::Client,
+}
+
+impl LocalLlamaCppTransformBackend {
+    pub fn new(server_url: String, token_budget: usize) -> Self {
+        Self {
+            server_url,
+            token_budget,
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    fn render_prompt(messages: &[Message]) -> String {
+        messages
+            .iter()
+            .map(|message| format!("{}: {}", message.role(), message.content()))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    async fn complete(&self, messages: Vec<Message>, temperature: f32) -> anyhow::Result<String> {
+        let prompt = Self::render_prompt(&messages);
+        let response = self
+            .http_client
+            .post(format!("{}/completion", self.server_url))
+            .json(&serde_json::json!({
+                "prompt": prompt,
+                "temperature": temperature,
+                "n_predict": self.token_budget,
+            }))
+            .send()
+            .await?;
+
+        let body: serde_json::Value = response.json().await?;
+
+        body.get("content")
+            .and_then(|content| content.as_str())
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow::anyhow!("llama.cpp server response had no `content` field"))
+    }
+}
+
+#[async_trait]
+impl TransformBackend for LocalLlamaCppTransformBackend {
+    async fn do_completion(&self, messages: Vec<Message>, temperature: f32) -> anyhow::Result<String> {
+        self.complete(messages, temperature).await
+    }
+
+    async fn do_generate_stream(
+        &self,
+        messages: Vec<Message>,
+        temperature: f32,
+        sender: UnboundedSender<String>,
+        _document_symbol: DocumentSymbol,
+    ) -> anyhow::Result<String> {
+        // The plain llama.cpp `/completion` endpoint isn't consumed as a
+        // stream here, so the best we can do is send the whole response as
+        // a single "delta" rather than dropping it silently.
+        let response = self.complete(messages, temperature).await?;
+        let _ = sender.send(response.clone());
+        Ok(response)
+    }
+
+    async fn do_completion_with_tools(
+        &self,
+        mut messages: Vec<Message>,
+        temperature: f32,
+        tools: Vec<serde_json::Value>,
+    ) -> anyhow::Result<String> {
+        // No native function-calling on the plain `/completion` endpoint,
+        // so we degrade to appending the tool schema as instructions and
+        // asking the model to reply with matching JSON directly.
+        if let Some(last_message) = messages.last_mut() {
+            let tools_json = serde_json::to_string_pretty(&tools).unwrap_or_else(|_| "[]".to_owned());
+            *last_message = Message::user(&format!(
+                "{}\n\nRespond with JSON matching one of these tool schemas:\n{}",
+                last_message.content(),
+                tools_json
+            ));
+        }
+        self.complete(messages, temperature).await
+    }
+}
+
+impl InLineAgent {
+    /// The backend this agent issues completions through: whatever was
+    /// configured via `with_transform_backend`, or an `OpenAITransformBackend`
+    /// wrapping `llm_client`/`model` otherwise (unchanged default behavior).
+    pub(super) fn backend(&self) -> Arc<dyn TransformBackend> {
+        self.transform_backend.clone().unwrap_or_else(|| {
+            Arc::new(OpenAITransformBackend::new(
+                self.llm_client.clone(),
+                self.model.clone(),
+            ))
+        })
+    }
+}
@@ -1,7 +1,9 @@
 use futures::stream;
 use futures::StreamExt;
+use llm_client::{clients::types::LLMType, tokenizer::tokenizer::LLMTokenizer};
 use std::sync::Arc;
 use tokio::sync::mpsc::{Sender, UnboundedSender};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     agent::{
@@ -19,6 +21,7 @@ use crate::{
 };
 
 use super::prompts;
+use super::transform_backend::TransformBackend;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct InLineAgentAnswer {
@@ -27,9 +30,14 @@ pub struct InLineAgentAnswer {
     pub state: MessageState,
     // We also send the document symbol in question along the wire
     pub document_symbol: Option<DocumentSymbol>,
+    // Populated for the Edit/Fix actions instead of a free-form answer -
+    // the validated edits `carry_out_edit_action` proposes for the
+    // selection.
+    #[serde(default)]
+    pub edit_operations: Option<Vec<super::action::EditOperation>>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum InLineAgentAction {
     // Add code to an already existing codebase
     Code,
@@ -56,6 +64,10 @@ pub enum MessageState {
     StreamingAnswer,
     Finished,
     Errored,
+    // A newer `ProcessInEditorRequest` for the same editor/session arrived
+    // before this one finished, so its `cancellation_token` was tripped and
+    // generation was abandoned without a final answer.
+    Cancelled,
 }
 
 impl Default for MessageState {
@@ -169,6 +181,24 @@ pub struct InLineAgent {
     // TODO(skcd): Break this out and don't use cross crate dependency like this
     editor_request: ProcessInEditorRequest,
     sender: Sender<InLineAgentMessage>,
+    // `None` by default, so every existing caller keeps going through
+    // `llm_client`/`model` exactly as before; set via `with_transform_backend`
+    // to route completions through something other than OpenAI (e.g. a local
+    // llama.cpp server).
+    transform_backend: Option<Arc<dyn TransformBackend>>,
+    // `None` by default, so prompts are built from the selection alone
+    // exactly as before; set via `with_memory_backend` to enrich them with
+    // extra repo context.
+    memory_backend: Option<Arc<dyn super::memory::MemoryBackend>>,
+    // The tokenizer `repo_context_message` caps its token budget against,
+    // set together with `memory_backend` via `with_memory_backend` - there's
+    // no accurate way to cap a context budget without knowing the model's
+    // real tokenization, so this travels with the backend rather than
+    // defaulting to some other model's tokenizer.
+    memory_context_tokenizer: Option<(Arc<LLMTokenizer>, LLMType)>,
+    // Per-action temperature/token budget, tunable without a rebuild; set
+    // via `with_configuration` to override `Configuration::default()`.
+    configuration: super::config::Configuration,
 }
 
 impl InLineAgent {
@@ -193,11 +223,27 @@ impl InLineAgent {
             sender,
             editor_request,
             editor_parsing,
+            transform_backend: None,
+            memory_backend: None,
+            memory_context_tokenizer: None,
+            configuration: super::config::Configuration::default(),
         }
     }
 
-    fn get_llm_client(&self) -> Arc<LlmClient> {
-        self.llm_client.clone()
+    /// Overrides the per-action temperature/token budget settings this
+    /// agent's actions read from, in place of `Configuration::default()`.
+    pub fn with_configuration(mut self, configuration: super::config::Configuration) -> Self {
+        self.configuration = configuration;
+        self
+    }
+
+    /// Routes this agent's completions through `transform_backend` instead
+    /// of the default `OpenAITransformBackend` wrapping `llm_client`/`model`
+    /// - e.g. a `LocalLlamaCppTransformBackend` for a deployment without an
+    /// OpenAI key.
+    pub fn with_transform_backend(mut self, transform_backend: Arc<dyn TransformBackend>) -> Self {
+        self.transform_backend = Some(transform_backend);
+        self
     }
 
     fn last_agent_message(&self) -> Option<&InLineAgentMessage> {
@@ -210,11 +256,23 @@ impl InLineAgent {
             .expect("There should always be a agent message")
     }
 
+    /// `cancellation_token` is tripped by the webserver layer when a newer
+    /// `ProcessInEditorRequest` arrives for the same editor/session, so a
+    /// user who keeps typing (or fires another inline command) doesn't leave
+    /// this run streaming tokens and burning API quota in the background.
+    /// It's checked here before deciding/generating anything, and threaded
+    /// down into `generate_documentation` so it can also be checked between
+    /// streamed chunks.
     pub async fn iterate(
         &mut self,
         action: InLineAgentAction,
         answer_sender: UnboundedSender<InLineAgentAnswer>,
+        cancellation_token: CancellationToken,
     ) -> anyhow::Result<Option<InLineAgentAction>> {
+        if cancellation_token.is_cancelled() {
+            self.get_last_agent_message().message_state = MessageState::Cancelled;
+            return Ok(None);
+        }
         match action {
             InLineAgentAction::DecideAction { query } => {
                 // Decide the action we are want to take here
@@ -241,7 +299,20 @@ impl InLineAgent {
                     self.sender.send(last_exchange.clone()).await?;
                 }
                 // and then we start generating the documentation
-                self.generate_documentation(answer_sender).await?;
+                self.generate_documentation(answer_sender, cancellation_token)
+                    .await?;
+                return Ok(None);
+            }
+            InLineAgentAction::Edit | InLineAgentAction::Fix => {
+                let last_exchange;
+                {
+                    let last_exchange_ref = self.get_last_agent_message();
+                    last_exchange_ref.add_agent_action(action.clone());
+                    last_exchange = last_exchange_ref.clone();
+                }
+                self.sender.send(last_exchange.clone()).await?;
+                self.carry_out_edit_action(action, answer_sender, cancellation_token)
+                    .await?;
                 return Ok(None);
             }
             _ => {
@@ -251,13 +322,91 @@ impl InLineAgent {
         }
     }
 
+    /// Proposes edits for the Edit/Fix actions against the selection the
+    /// user's `ProcessInEditorRequest` points at, streaming the validated
+    /// edits back over `answer_sender` as a single `InLineAgentAnswer` (or
+    /// an apology when the backend's reply didn't yield anything usable).
+    /// `cancellation_token` is checked before issuing the completion and
+    /// before sending the answer, mirroring `generate_documentation`.
+    async fn carry_out_edit_action(
+        &mut self,
+        action: InLineAgentAction,
+        answer_sender: UnboundedSender<InLineAgentAnswer>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        if cancellation_token.is_cancelled() {
+            self.get_last_agent_message().message_state = MessageState::Cancelled;
+            let _ = answer_sender.send(InLineAgentAnswer {
+                answer_up_until_now: String::new(),
+                delta: None,
+                state: MessageState::Cancelled,
+                document_symbol: None,
+                edit_operations: None,
+            });
+            return Ok(());
+        }
+
+        let source_str = self.editor_request.text_document_web.text.to_owned();
+        let start_position = self
+            .editor_request
+            .snippet_information
+            .start_position
+            .clone();
+        let end_position = self.editor_request.snippet_information.end_position.clone();
+        let selection =
+            source_str[start_position.byte_offset()..end_position.byte_offset()].to_owned();
+        let instruction = self.editor_request.query.to_owned();
+
+        let edit_operations = self
+            .edit_operations_for_action(action, &selection, &instruction)
+            .await?;
+
+        if cancellation_token.is_cancelled() {
+            self.get_last_agent_message().message_state = MessageState::Cancelled;
+            let _ = answer_sender.send(InLineAgentAnswer {
+                answer_up_until_now: String::new(),
+                delta: None,
+                state: MessageState::Cancelled,
+                document_symbol: None,
+                edit_operations: None,
+            });
+            return Ok(());
+        }
+
+        let last_exchange = self.get_last_agent_message();
+        match edit_operations {
+            Some(edit_operations) => {
+                last_exchange.message_state = MessageState::Finished;
+                let _ = answer_sender.send(InLineAgentAnswer {
+                    answer_up_until_now: String::new(),
+                    delta: None,
+                    state: MessageState::Finished,
+                    document_symbol: None,
+                    edit_operations: Some(edit_operations),
+                });
+            }
+            None => {
+                let apology = super::action::apologise_message();
+                last_exchange.message_state = MessageState::Errored;
+                let _ = answer_sender.send(InLineAgentAnswer {
+                    answer_up_until_now: apology.clone(),
+                    delta: Some(apology),
+                    state: MessageState::Errored,
+                    document_symbol: None,
+                    edit_operations: None,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     async fn decide_action(&mut self, query: &str) -> anyhow::Result<InLineAgentAction> {
-        let model = llm_funcs::llm::OpenAIModel::get_model(self.model.model_name)?;
         let system_prompt = prompts::decide_function_to_use(query);
         let messages = vec![llm_funcs::llm::Message::system(&system_prompt)];
         let response = self
-            .get_llm_client()
-            .response(model, messages, None, 0.0, None)
+            .backend()
+            .do_completion(messages, self.configuration.decide_action.temperature)
             .await?;
         let last_exchange = self.get_last_agent_message();
         // We add that we took a action to decide what we should do next
@@ -270,6 +419,7 @@ impl InLineAgent {
     async fn generate_documentation(
         &mut self,
         answer_sender: UnboundedSender<InLineAgentAnswer>,
+        cancellation_token: CancellationToken,
     ) -> anyhow::Result<()> {
         // Now we get to the documentation generation loop, here we want to
         // first figure out what the context of the document is which we want
@@ -310,6 +460,7 @@ impl InLineAgent {
                 delta: Some("could not find documentation node".to_owned()),
                 state: MessageState::Errored,
                 document_symbol: None,
+                edit_operations: None,
             })?;
         } else {
             last_exchange.message_state = MessageState::StreamingAnswer;
@@ -321,33 +472,90 @@ impl InLineAgent {
             );
             let self_ = &*self;
             stream::iter(messages_list)
-                .map(|messages| (messages, answer_sender.clone()))
-                .for_each(|((messages, document_symbol), answer_sender)| async move {
-                    let (proxy_sender, _proxy_receiver) = tokio::sync::mpsc::unbounded_channel();
+                .map(|messages| {
+                    (
+                        messages,
+                        answer_sender.clone(),
+                        cancellation_token.clone(),
+                        fs_file_path.clone(),
+                    )
+                })
+                .for_each(|((messages, document_symbol), answer_sender, cancellation_token, fs_file_path)| async move {
+                    if cancellation_token.is_cancelled() {
+                        let _ = answer_sender.send(InLineAgentAnswer {
+                            answer_up_until_now: String::new(),
+                            delta: None,
+                            state: MessageState::Cancelled,
+                            document_symbol: Some(document_symbol.clone()),
+                            edit_operations: None,
+                        });
+                        return;
+                    }
+
+                    // `proxy_receiver` used to be dropped immediately, so the
+                    // only answer the editor ever saw was the single message
+                    // sent once the whole completion had finished. Forward
+                    // each chunk as it arrives instead, so the editor can
+                    // render incrementally the same way chat streaming does.
+                    let (proxy_sender, mut proxy_receiver) =
+                        tokio::sync::mpsc::unbounded_channel::<String>();
+                    let forward_document_symbol = document_symbol.clone();
+                    let forward_answer_sender = answer_sender.clone();
+                    let forward_cancellation_token = cancellation_token.clone();
+                    let forward_deltas = tokio::spawn(async move {
+                        let mut answer_up_until_now = String::new();
+                        while let Some(delta) = proxy_receiver.recv().await {
+                            if forward_cancellation_token.is_cancelled() {
+                                break;
+                            }
+                            answer_up_until_now.push_str(&delta);
+                            let _ = forward_answer_sender.send(InLineAgentAnswer {
+                                answer_up_until_now: answer_up_until_now.clone(),
+                                delta: Some(delta),
+                                state: MessageState::StreamingAnswer,
+                                document_symbol: Some(forward_document_symbol.clone()),
+                                edit_operations: None,
+                            });
+                        }
+                    });
+
+                    let mut llm_messages = messages.messages;
+                    if let Some(context_message) =
+                        self_.repo_context_message(&fs_file_path, &document_symbol).await
+                    {
+                        llm_messages.push(context_message);
+                    }
+
                     let answer = self_
-                        .get_llm_client()
-                        .stream_response_inline_agent(
-                            llm_funcs::llm::OpenAIModel::get_model(&self_.model.model_name)
-                                .expect("openai model getting to always work"),
-                            messages.messages,
-                            None,
-                            0.2,
-                            None,
+                        .backend()
+                        .do_generate_stream(
+                            llm_messages,
+                            self_.configuration.doc.temperature,
                             proxy_sender,
                             document_symbol.clone(),
                         )
                         .await;
-                    // we send the answer after we have generated the whole thing
-                    // not in between as its not proactive updates
-                    if let Ok(answer) = answer {
-                        answer_sender
-                            .send(InLineAgentAnswer {
-                                answer_up_until_now: answer.to_owned(),
-                                delta: Some(answer.to_owned()),
-                                state: Default::default(),
-                                document_symbol: Some(document_symbol.clone()),
-                            })
-                            .unwrap();
+                    // dropping our end of the channel (above, once `answer`
+                    // resolves) is what lets `forward_deltas` drain the rest
+                    // of the buffered chunks and return
+                    let _ = forward_deltas.await;
+
+                    if cancellation_token.is_cancelled() {
+                        let _ = answer_sender.send(InLineAgentAnswer {
+                            answer_up_until_now: String::new(),
+                            delta: None,
+                            state: MessageState::Cancelled,
+                            document_symbol: Some(document_symbol.clone()),
+                            edit_operations: None,
+                        });
+                    } else if let Ok(answer) = answer {
+                        let _ = answer_sender.send(InLineAgentAnswer {
+                            answer_up_until_now: answer.to_owned(),
+                            delta: None,
+                            state: MessageState::Finished,
+                            document_symbol: Some(document_symbol.clone()),
+                            edit_operations: None,
+                        });
                     }
                 })
                 .await;
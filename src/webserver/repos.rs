@@ -63,6 +63,14 @@ pub struct RepoParams {
 impl ApiResponse for ReposResponse {}
 
 /// Synchronize a repo by its id
+///
+/// There's no `crawl_all_files` query param here: wiring it through would
+/// mean building a `Repository`/`TagIndex` for this repo inside the
+/// webserver layer, and neither of those (nor the indexer that would own
+/// them) exists in this tree to build against. The ignore-aware crawler
+/// itself (`Crawl::ensure_crawled`) is real and already exercised by the
+/// lazy per-extension path in `repository.rs`'s `execute_search` - it's
+/// only the sync-route wiring that's waiting on the real indexer API.
 pub async fn sync(
     Query(RepoParams { repo }): Query<RepoParams>,
     State(app): State<Application>,